@@ -0,0 +1,259 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Local cache of remote eopkg indexes
+//!
+//! Each index is stored alongside the HTTP validators it was served with
+//! and a parsed snapshot, so an unchanged index is neither downloaded
+//! nor parsed again. Fresh downloads are verified against the published
+//! `.sha1sum` before they replace the cached copy.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+    time::UNIX_EPOCH,
+};
+
+use reqwest::{
+    header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+    eopkg::index::Index,
+    http::{self, Client},
+};
+
+/// HTTP validators recorded for a cached index
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Snapshots are only trusted when written by this same release, since
+/// the layout of [`Index`] may change between releases
+const SNAPSHOT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Parsed index as stored on disk
+#[derive(Deserialize)]
+struct Snapshot {
+    version: String,
+    index: Index,
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: &'a str,
+    index: &'a Index,
+}
+
+/// Outcome of refreshing a cached index
+#[derive(Debug)]
+pub struct Refreshed {
+    /// Local copy of the index
+    pub path: PathBuf,
+
+    /// Whether the index changed since it was last cached
+    pub changed: bool,
+}
+
+impl Refreshed {
+    /// Hex encoded sha256 of the local copy, identifying the snapshot
+    pub fn sha256(&self) -> Result<String, Error> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(&self.path)?, &mut hasher)?;
+        Ok(const_hex::encode(hasher.finalize()))
+    }
+}
+
+#[derive(Clone)]
+pub struct IndexCache {
+    dir: PathBuf,
+}
+
+impl IndexCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Cache entries are keyed by a digest of the index URI
+    fn entry(&self, uri: &Url, extension: &str) -> PathBuf {
+        let digest = Sha256::digest(uri.as_str());
+        self.dir.join(format!(
+            "index-{}.{extension}",
+            const_hex::encode(&digest[..8])
+        ))
+    }
+
+    fn validators(&self, uri: &Url) -> Option<Validators> {
+        let file = File::open(self.entry(uri, "validators.json")).ok()?;
+        serde_json::from_reader(BufReader::new(file)).ok()
+    }
+
+    fn store_validators(&self, uri: &Url, validators: &Validators) -> Result<(), Error> {
+        let file = File::create(self.entry(uri, "validators.json"))?;
+        serde_json::to_writer(file, validators)?;
+        Ok(())
+    }
+
+    /// Local indexes are used in place, with their size and modification
+    /// time standing in for an ETag
+    fn refresh_local(&self, uri: &Url) -> Result<Refreshed, Error> {
+        let path = uri.to_file_path().map_err(|_| Error::InvalidPath)?;
+        let meta = path.metadata()?;
+        let modified = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let validators = Validators {
+            etag: Some(format!("{}-{modified}", meta.len())),
+            last_modified: None,
+        };
+
+        let changed = self.validators(uri).and_then(|v| v.etag) != validators.etag;
+        if changed {
+            let _ = fs::remove_file(self.entry(uri, "json"));
+            self.store_validators(uri, &validators)?;
+        }
+
+        Ok(Refreshed { path, changed })
+    }
+
+    /// Conditionally fetch `uri`, only downloading it when the server reports
+    /// a change since the cached copy
+    pub async fn refresh(&self, client: &Client, uri: &Url) -> Result<Refreshed, Error> {
+        if uri.scheme() == "file" {
+            return self.refresh_local(uri);
+        }
+
+        let path = self.entry(uri, "index");
+        let validators = if path.exists() {
+            self.validators(uri).unwrap_or_default()
+        } else {
+            Validators::default()
+        };
+
+        let mut request = client.get(uri.clone());
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+        }
+
+        let response = client.read(request.send()).await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Refreshed {
+                path,
+                changed: false,
+            });
+        }
+        let mut response = response.error_for_status()?;
+
+        let header = |name: HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+
+        // The snapshot no longer matches once the index is replaced
+        let _ = fs::remove_file(self.entry(uri, "json"));
+
+        let partial = self.entry(uri, "part");
+        let mut output = File::create(&partial)?;
+        let mut hasher = Sha1::new();
+        while let Some(chunk) = client.read(response.chunk()).await? {
+            output.write_all(&chunk)?;
+            hasher.update(&chunk);
+        }
+
+        let expected = published_sha1sum(client, uri).await?;
+        let actual = const_hex::encode(hasher.finalize());
+        if !actual.eq_ignore_ascii_case(&expected) {
+            fs::remove_file(&partial)?;
+            return Err(Error::Checksum { expected, actual });
+        }
+        fs::rename(&partial, &path)?;
+        self.store_validators(uri, &validators)?;
+
+        Ok(Refreshed {
+            path,
+            changed: true,
+        })
+    }
+
+    /// Previously parsed index for `uri`, if one was stored by this release
+    /// and still loads
+    pub fn snapshot(&self, uri: &Url) -> Option<Index> {
+        let file = File::open(self.entry(uri, "json")).ok()?;
+        let snapshot: Snapshot = serde_json::from_reader(BufReader::new(file)).ok()?;
+        (snapshot.version == SNAPSHOT_VERSION).then_some(snapshot.index)
+    }
+
+    /// Store the parsed index for `uri` so unchanged indexes skip parsing
+    pub fn store_snapshot(&self, uri: &Url, index: &Index) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(self.entry(uri, "json"))?);
+        let snapshot = SnapshotRef {
+            version: SNAPSHOT_VERSION,
+            index,
+        };
+        serde_json::to_writer(&mut writer, &snapshot)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Fetch the `.sha1sum` published next to the index at `uri`
+async fn published_sha1sum(client: &Client, uri: &Url) -> Result<String, Error> {
+    let uri = Url::parse(&format!("{uri}.sha1sum"))?;
+    let response = client.read(client.get(uri).send()).await?;
+    let body = client.read(response.error_for_status()?.text()).await?;
+
+    // Format is `<digest>` optionally followed by the file name
+    body.split_whitespace()
+        .next()
+        .map(String::from)
+        .ok_or(Error::MissingChecksum)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("http: {0}")]
+    Http(#[from] http::Error),
+
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("uri parse: {0}")]
+    URI(#[from] url::ParseError),
+
+    #[error("invalid file uri")]
+    InvalidPath,
+
+    #[error("empty index checksum")]
+    MissingChecksum,
+
+    #[error("index checksum mismatch: expected {expected}, got {actual}")]
+    Checksum { expected: String, actual: String },
+}
@@ -0,0 +1,94 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! User configuration, loaded from a YAML file
+
+use std::{collections::BTreeMap, env, fs::File, path::Path};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::converter::{Bucketing, MismatchPolicy, RecipeOptions};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Credentials for mirrors requiring authentication
+    pub credentials: Vec<Credential>,
+
+    /// Extra license mappings from eopkg strings to SPDX expressions
+    pub licenses: BTreeMap<String, String>,
+
+    /// Serpent OS names for eopkg packages and sources, such as
+    /// `libgcrypt: gcrypt`
+    pub renames: BTreeMap<String, String>,
+
+    /// Whether recipes are generated per source or per package
+    pub bucketing: Option<Bucketing>,
+
+    /// Handling of the packages of a recipe being at different releases
+    pub mismatch: Option<MismatchPolicy>,
+
+    /// Serpent OS components for eopkg ones, such as
+    /// `system.devel: system.devel`. Subcomponents fall back to the
+    /// mapping of their parent.
+    pub components: BTreeMap<String, String>,
+
+    /// Settings such as `strip` and `toolchain`, applied to every recipe
+    pub recipe: RecipeOptions,
+
+    /// Settings of individual recipes, keyed by their eopkg source name,
+    /// or package name when bucketing per package
+    pub overrides: BTreeMap<String, RecipeOptions>,
+
+    /// Components to convert, such as `system.base` or `system.*`, unless
+    /// given on the command line
+    pub selection: Vec<String>,
+}
+
+/// Basic auth or bearer token credentials for a host
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Credential {
+    /// Host these credentials are sent to, or every host when unset
+    pub host: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+
+    /// Bearer token, preferred over basic auth when both are set
+    pub token: Option<String>,
+}
+
+impl Config {
+    /// Load the configuration from `path`
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Ok(serde_yaml::from_reader(File::open(path)?)?)
+    }
+}
+
+impl Credential {
+    /// Credentials supplied via the `PISI_AUTH_*` environment variables
+    pub fn from_env() -> Option<Self> {
+        let username = env::var("PISI_AUTH_USER").ok();
+        let token = env::var("PISI_AUTH_TOKEN").ok();
+        if username.is_none() && token.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            host: env::var("PISI_AUTH_HOST").ok(),
+            username,
+            password: env::var("PISI_AUTH_PASSWORD").ok(),
+            token,
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("yaml: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+}
@@ -4,78 +4,1256 @@
 
 //! Convert input package to a yaml file
 
-use std::{path::PathBuf, vec};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    path::PathBuf,
+    str::FromStr,
+    vec,
+};
 
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use url::Url;
 
-use crate::eopkg::index::Package;
+use crate::{
+    eopkg::{
+        files::Files,
+        index::{component_parent, Package, Update},
+        pspec::Pspec,
+    },
+    license::Licenses,
+    recipe::{Profile, Recipe, Subpackage, Upstream},
+};
 
+/// Top-level keys of a recipe that follow from the eopkgs, and so are
+/// regenerated when merging into an existing recipe
+const REGENERATED: &[&str] = &["version", "release", "upstreams"];
+
+/// Build target of the 32-bit profile
+const EMUL32_TARGET: &str = "emul32/x86_64";
+
+/// Legacy locations and where they live on a usr-merged system, longest
+/// first so `/lib64` is not mistaken for `/lib`
+const USR_MERGE: &[(&str, &str)] = &[
+    ("/usr/sbin", "/usr/bin"),
+    ("/lib64", "/usr/lib64"),
+    ("/lib32", "/usr/lib32"),
+    ("/sbin", "/usr/bin"),
+    ("/bin", "/usr/bin"),
+    ("/lib", "/usr/lib"),
+];
+
+/// Locations whose contents always move as a whole
+const LOCATIONS: &[&str] = &[
+    "/usr/include",
+    "/usr/lib32/pkgconfig",
+    "/usr/lib64/cmake",
+    "/usr/lib64/pkgconfig",
+    "/usr/lib/debug",
+    "/usr/share/doc",
+    "/usr/share/gtk-doc",
+    "/usr/share/info",
+    "/usr/share/man",
+    "/usr/share/pkgconfig",
+];
+
+/// Paths of the usual eopkg subpackages, used when files.xml is unavailable
+const SPLITS: &[(&str, &[&str])] = &[
+    (
+        "-32bit-devel",
+        &["/usr/lib32/lib*.so", "/usr/lib32/pkgconfig"],
+    ),
+    ("-32bit", &["/usr/lib32"]),
+    ("-dbginfo", &["/usr/lib/debug"]),
+    (
+        "-devel",
+        &[
+            "/usr/include",
+            "/usr/lib64/cmake",
+            "/usr/lib64/lib*.so",
+            "/usr/lib64/pkgconfig",
+            "/usr/share/pkgconfig",
+        ],
+    ),
+    (
+        "-docs",
+        &[
+            "/usr/share/doc",
+            "/usr/share/gtk-doc",
+            "/usr/share/info",
+            "/usr/share/man",
+        ],
+    ),
+];
+
+/// How converted packages are grouped into recipes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucketing {
+    /// One recipe per source, with the other packages split off
+    #[default]
+    Source,
+    /// One recipe per binary package
+    Package,
+}
+
+impl Bucketing {
+    /// Name of the recipe `package` is converted into
+    pub fn key<'a>(&self, package: &'a Package) -> &'a str {
+        match self {
+            Bucketing::Source => &package.source.name,
+            Bucketing::Package => &package.name,
+        }
+    }
+}
+
+impl FromStr for Bucketing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "source" => Ok(Bucketing::Source),
+            "package" => Ok(Bucketing::Package),
+            _ => Err(Error::InvalidBucketing(s.to_string())),
+        }
+    }
+}
+
+/// Recipe level settings, left to boulder's defaults when unset
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RecipeOptions {
+    /// Strip the installed binaries, off unless set as the eopkg payloads
+    /// are already stripped
+    pub strip: Option<bool>,
+    /// Allow network access during the build
+    pub networking: Option<bool>,
+    /// Toolchain to build with, `llvm` or `gnu`
+    pub toolchain: Option<String>,
+}
+
+impl RecipeOptions {
+    /// These settings with the ones set in `overrides` taking precedence
+    pub fn with(&self, overrides: &RecipeOptions) -> RecipeOptions {
+        RecipeOptions {
+            strip: overrides.strip.or(self.strip),
+            networking: overrides.networking.or(self.networking),
+            toolchain: overrides.toolchain.clone().or(self.toolchain.clone()),
+        }
+    }
+}
+
+/// What to do when the packages of a recipe are at different releases
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MismatchPolicy {
+    /// Use the release of the main package and warn
+    #[default]
+    Warn,
+    /// Use the highest release among the packages and warn
+    Highest,
+    /// Fail the conversion
+    Fail,
+}
+
+impl FromStr for MismatchPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(MismatchPolicy::Warn),
+            "highest" => Ok(MismatchPolicy::Highest),
+            "fail" => Ok(MismatchPolicy::Fail),
+            _ => Err(Error::InvalidMismatchPolicy(s.to_string())),
+        }
+    }
+}
+
+/// Where recipes are written within the output directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Directly under the output, as `<source>/stone.yml`
+    #[default]
+    Flat,
+    /// As in the Serpent OS recipes repository, `<initial>/<source>/stone.yml`
+    Recipes,
+}
+
+impl Layout {
+    /// Directory holding the recipe of `name`, relative to the output
+    pub fn path(&self, name: &str) -> PathBuf {
+        match self {
+            Layout::Flat => PathBuf::from(name),
+            Layout::Recipes => {
+                let initial = name.chars().next().unwrap_or('_').to_ascii_lowercase();
+                PathBuf::from(initial.to_string()).join(name)
+            }
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(Layout::Flat),
+            "recipes" => Ok(Layout::Recipes),
+            _ => Err(Error::InvalidLayout(s.to_string())),
+        }
+    }
+}
+
+/// How the `release` of a recipe is derived from the eopkg one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReleasePolicy {
+    /// Carry over the eopkg release
+    #[default]
+    Keep,
+    /// Restart counting at 1
+    Reset,
+    /// Shift the eopkg release, never going below 1
+    Offset(i64),
+}
+
+impl ReleasePolicy {
+    /// Release of the recipe for the eopkg `release`
+    pub fn apply(&self, release: u64) -> u64 {
+        match self {
+            ReleasePolicy::Keep => release,
+            ReleasePolicy::Reset => 1,
+            ReleasePolicy::Offset(offset) => release.saturating_add_signed(*offset).max(1),
+        }
+    }
+}
+
+impl FromStr for ReleasePolicy {
+    type Err = Error;
+
+    /// Either `keep`, `reset` or a signed offset such as `-100`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(ReleasePolicy::Keep),
+            "reset" => Ok(ReleasePolicy::Reset),
+            _ => s
+                .parse()
+                .map(ReleasePolicy::Offset)
+                .map_err(|_| Error::InvalidReleasePolicy(s.to_string())),
+        }
+    }
+}
+
+/// Options controlling the generated recipes
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Preferred language for summaries and descriptions
+    pub language: String,
+    /// Append the package history to the recipe as comments
+    pub changelog: bool,
+    /// Mapping of eopkg license strings to SPDX
+    pub licenses: Licenses,
+    /// Keep `-dbginfo` packages among the upstreams
+    pub keep_dbginfo: bool,
+    /// Tera template rendering the recipe in place of the default layout
+    pub template: Option<String>,
+    /// Serpent OS names of eopkg packages and sources
+    pub renames: BTreeMap<String, String>,
+    /// Serpent OS components of eopkg ones
+    pub components: BTreeMap<String, String>,
+    /// Relocate legacy `/bin`, `/sbin` and `/lib` contents into `/usr`
+    pub usr_merge: bool,
+    /// How packages were grouped into the recipe
+    pub bucketing: Bucketing,
+    /// Where repacked payloads are published, if they are used
+    pub repack_uri: Option<Url>,
+    /// How the eopkg release is carried over
+    pub release: ReleasePolicy,
+    /// Build from the upstream archives of the pspec when it is known,
+    /// rather than repackaging the binaries
+    pub source: bool,
+    /// Settings applied to every recipe
+    pub recipe: RecipeOptions,
+    /// Settings of individual recipes, keyed by their eopkg name
+    pub overrides: BTreeMap<String, RecipeOptions>,
+    /// Date of the index snapshot the recipes are generated from
+    pub snapshot: Option<NaiveDate>,
+    /// Handling of packages at different releases within a recipe
+    pub mismatch: MismatchPolicy,
+    /// Append the sha1 and sha256 of each eopkg to the recipe as comments
+    pub checksums: bool,
+}
+
+impl Options {
+    /// Name of a package or source once converted
+    pub fn rename(&self, name: &str) -> String {
+        self.renames
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Serpent OS component of the eopkg `part_of`, using the mapping of
+    /// the closest mapped parent
+    pub fn component(&self, part_of: &str) -> Option<&str> {
+        let mut component = Some(part_of);
+        while let Some(name) = component {
+            if let Some(mapped) = self.components.get(name) {
+                return Some(mapped);
+            }
+            component = component_parent(name);
+        }
+        None
+    }
+}
+
+/// A generated recipe along with anything that needs manual review
+#[derive(Debug, Clone, Default)]
+pub struct Converted {
+    pub recipe: String,
+    pub warnings: Vec<String>,
+    /// Files moved by the usr-merge, as `from -> to`
+    pub relocated: Vec<String>,
+    /// Stub `monitoring.yaml` to fill in by hand
+    pub monitoring: String,
+    /// The eopkgs the recipe was generated from, in name order
+    pub origins: Vec<Origin>,
+    /// Summary of the recipe, with the path left for the caller to fill in
+    pub entry: ManifestEntry,
+}
+
+/// Every recipe generated in a run, written as `manifest.json`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Manifest {
+    /// Version of the converter that generated the recipes
+    pub converter: String,
+    pub recipes: Vec<ManifestEntry>,
+}
+
+/// Summary of a single generated recipe
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub version: String,
+    pub release: u64,
+    /// Names of the packages the recipe produces, once renamed
+    pub packages: Vec<String>,
+    /// Directory of the recipe, relative to the output
+    pub path: PathBuf,
+}
+
+/// Where a generated recipe came from, written as `provenance.json`
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    /// Version of the converter that generated the recipe
+    pub converter: String,
+    /// Indexes the packages were resolved from
+    pub indexes: Vec<IndexSnapshot>,
+    pub packages: Vec<Origin>,
+}
+
+/// An index as it was when the recipe was generated
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexSnapshot {
+    pub uri: String,
+    pub sha256: String,
+}
+
+/// The eopkg a recipe was generated from
+#[derive(Debug, Clone, Serialize)]
+pub struct Origin {
+    pub name: String,
+    pub source: String,
+    pub version: Option<String>,
+    pub release: Option<u64>,
+    pub uri: String,
+    /// sha1 of the archive, as published in the index
+    pub package_hash: String,
+    /// sha256 of the archive, as downloaded
+    pub sha256: String,
+    /// sha256 of the repacked payload, when used as the upstream
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_sha256: Option<String>,
+}
+
+#[derive(Clone)]
 pub struct HashedPackage {
     /// Finalised hash
     pub hash: [u8; 32],
 
     /// Actual package itself
     pub package: Package,
+
+    /// Contents of the archive, once read
+    pub files: Option<Files>,
+
+    /// Payload repacked as a standalone tarball
+    pub payload: Option<Payload>,
+}
+
+/// The `install.tar.xz` of a package, published on its own
+#[derive(Debug, Clone)]
+pub struct Payload {
+    pub file_name: String,
+    pub hash: [u8; 32],
 }
 
 /// For the given input packages, yield a functioning
 /// boulder recipe as a string
-pub fn convert(input: Vec<&HashedPackage>, base_uri: Url) -> Result<String, Error> {
-    let mut upstreams = vec![];
+///
+/// When the `pspec` of the source is known its build dependencies are
+/// carried over, otherwise they are guessed from the `-devel` dependencies.
+/// In source mode its archives become the upstreams, which must have been
+/// hashed beforehand.
+pub fn convert(
+    input: Vec<&HashedPackage>,
+    base_uri: Url,
+    pspec: Option<&Pspec>,
+    options: &Options,
+) -> Result<Converted, Error> {
+    // Order by name so regenerated recipes diff cleanly
+    let mut input = input
+        .into_iter()
+        .filter(|p| options.keep_dbginfo || !p.package.name.is_dbginfo())
+        .collect::<Vec<_>>();
+    input.sort_by(|a, b| {
+        a.package
+            .name
+            .cmp(&b.package.name)
+            .then_with(|| a.package.package_uri.cmp(&b.package.package_uri))
+    });
+    let mut warnings = vec![];
+
+    // Overlapping selections can yield the same archive twice
+    let selected = input.len();
+    input.dedup_by(|a, b| a.package.package_uri == b.package.package_uri && a.hash == b.hash);
+    if input.len() < selected {
+        warnings.push(format!(
+            "dropped {} duplicate packages",
+            selected - input.len()
+        ));
+    }
+
+    // Updates without a readable date are left out of the changelog dates
     for pkg in input.iter() {
-        let uri = base_uri.join(&pkg.package.package_uri)?.to_string();
-        upstreams.push(format!(
-            " - {}:\n    unpack: false\n    hash: {}",
-            uri,
-            const_hex::encode(pkg.hash)
+        let history = &pkg.package.history;
+        for update in history.updates.iter().filter(|u| u.date().is_none()) {
+            warnings.push(format!(
+                "{} release {} has an unreadable date {:?}",
+                pkg.package.name, update.release, update.raw_date
+            ));
+        }
+    }
+    let mut upstreams = vec![];
+    let source_build = pspec
+        .filter(|p| options.source && !p.source.archives.is_empty())
+        .map(|p| -> Result<_, Error> {
+            for archive in p.source.archives.iter() {
+                let hash = archive
+                    .sha256
+                    .ok_or_else(|| Error::UnhashedArchive(archive.uri.clone()))?;
+                let upstream = Upstream {
+                    unpack: true,
+                    hash: const_hex::encode(hash),
+                    ..Default::default()
+                };
+                upstreams.push(BTreeMap::from([(archive.uri.trim().to_string(), upstream)]));
+            }
+            Ok(p)
+        })
+        .transpose()?;
+    if options.source && source_build.is_none() {
+        warnings.push(format!(
+            "{} has no known upstream archives, repackaging the binaries",
+            input.first().ok_or(Error::NoPackage)?.package.source.name
         ));
     }
+    let names = source_names(&input, &base_uri)?;
+    for pkg in input.iter().filter(|_| source_build.is_none()) {
+        let (uri, upstream) = match repacked(pkg, options) {
+            Some((uri, payload)) => (
+                uri.join(&payload.file_name)?,
+                Upstream {
+                    unpack: true,
+                    hash: const_hex::encode(payload.hash),
+                    unpackdir: Some(pkg.package.name.to_string()),
+                    stripdirs: Some(0),
+                    ..Default::default()
+                },
+            ),
+            None => {
+                let uri = pkg.package.uri(&base_uri)?;
+                let rename = names
+                    .get(uri.as_str())
+                    .filter(|name| !uri.path().ends_with(&format!("/{name}")))
+                    .cloned();
+                (
+                    uri,
+                    Upstream {
+                        unpack: false,
+                        hash: const_hex::encode(pkg.hash),
+                        rename,
+                        ..Default::default()
+                    },
+                )
+            }
+        };
+        upstreams.push(BTreeMap::from([(uri.to_string(), upstream)]));
+    }
+    let mut seen = BTreeSet::new();
+    upstreams.retain(|u| {
+        u.iter()
+            .all(|(uri, upstream)| seen.insert((uri.clone(), upstream.hash.clone())))
+    });
+
+    // 32-bit payloads are only installed by the emul32 profile
+    let (emul32, native): (Vec<_>, Vec<_>) = input
+        .iter()
+        .copied()
+        .partition(|p| p.package.name.is_emul32());
+    let mut profiles = vec![];
+    if !emul32.is_empty() && source_build.is_none() {
+        let profile = Profile {
+            install: generate_install_script(&emul32, &base_uri, &names, options)?,
+            ..Default::default()
+        };
+        profiles.push(BTreeMap::from([(EMUL32_TARGET.to_string(), profile)]));
+    }
+
+    // The package named after the source carries the recipe metadata
+    let sample = input
+        .iter()
+        .find(|p| p.package.name.as_str() == p.package.source.name.as_str())
+        .or(input.first())
+        .ok_or(Error::NoPackage)?;
+    let source = options.rename(options.bucketing.key(&sample.package));
+    let mut packages = vec![];
+    for pkg in input
+        .iter()
+        .filter(|p| p.package.name != sample.package.name)
+    {
+        let name = &pkg.package.name;
+        let paths = match &pkg.files {
+            Some(files) => patterns(files, options.usr_merge),
+            None => SPLITS
+                .iter()
+                .find(|(suffix, _)| name.ends_with(suffix))
+                .map(|(_, paths)| paths.iter().map(|p| p.to_string()).collect())
+                .unwrap_or_default(),
+        };
+        if paths.is_empty() {
+            warnings.push(format!("{name} has no known paths to split off"));
+        }
+        // Each eopkg carries its own summary, which is worth keeping over
+        // the generic one of the recipe
+        let summary = match pkg.package.summary.get(&options.language) {
+            "" => sample.package.summary.get(&options.language),
+            summary => summary,
+        };
+        let description = block_text(pkg.package.description.get(&options.language));
+        let subpackage = Subpackage {
+            summary: summary.to_string(),
+            description: (description.trim() != ""
+                && description != block_text(sample.package.description.get(&options.language)))
+            .then_some(description),
+            rundeps: run_deps(pkg, options),
+            conflicts: conflicts(pkg, options),
+            paths,
+        };
+        let renamed = options.rename(name);
+        let key = match renamed
+            .strip_prefix(source.as_str())
+            .filter(|s| s.starts_with('-'))
+        {
+            Some(suffix) => format!("%(name){suffix}"),
+            None => renamed.clone(),
+        };
+        packages.push(BTreeMap::from([(key, subpackage)]));
+    }
 
-    let sample = &input.first().ok_or(Error::NoPackage)?;
     let homepage = sample
         .package
         .source
         .homepage
         .clone()
         .unwrap_or("no-homepage-set".into());
-    let licenses = sample.package.licenses.iter().map(|l| format!("    - {l}"));
-    let yml = vec![
-        format!("name: {}", sample.package.source.name),
-        format!("version: \"{}\"", sample.package.history.updates[0].version),
-        format!("release: {}", sample.package.history.updates[0].release),
-        format!("homepage: {}", homepage),
-        "upstreams:".into(),
-        upstreams.join("\n"),
-        format!("summary: {}", sample.package.summary),
-        format!(
-            "description: |\n    {}",
-            sample.package.description.replace('\n', " ")
-        ),
-        "strip: false".into(),
-        "license: ".into(),
-        licenses.collect::<Vec<String>>().join("\n"),
-        "install:  |".into(),
-        generate_install_script(&input, &base_uri)?,
+    if sample.package.licenses.is_empty() {
+        warnings.push(format!("{} declares no license", sample.package.name));
+    }
+    let licenses = options
+        .licenses
+        .normalize(sample.package.licenses.iter().map(String::as_str));
+    for unknown in licenses.unknown.iter() {
+        warnings.push(format!(
+            "{} declares unknown license {unknown}",
+            sample.package.name
+        ));
+    }
+    // Stateless policy keeps defaults out of /etc
+    for pkg in input.iter() {
+        let configs = pkg.files.iter().flat_map(|f| f.files.iter());
+        for config in configs.filter(|f| f.is_config() && f.path.starts_with("etc/")) {
+            warnings.push(format!(
+                "{} ships config file /{}, consider moving it to /usr/share/defaults",
+                pkg.package.name, config.path
+            ));
+        }
+    }
+
+    // Partial rebuilds leave packages of a source at different releases
+    let latest = match options.mismatch {
+        MismatchPolicy::Highest => input
+            .iter()
+            .filter_map(|p| p.package.history.latest())
+            .max_by_key(|u| (u.release, u.to_release())),
+        MismatchPolicy::Warn | MismatchPolicy::Fail => sample.package.history.latest(),
+    };
+    let describe = |update: Option<&Update>| {
+        update.map_or("unknown".to_string(), |u| {
+            format!("{}-{}", u.version, u.release)
+        })
+    };
+    for pkg in input.iter() {
+        let update = pkg.package.history.latest();
+        if update.map(Update::to_release) != latest.map(Update::to_release) {
+            let mismatch = format!(
+                "{} is at {}, the recipe at {}",
+                pkg.package.name,
+                describe(update),
+                describe(latest),
+            );
+            if options.mismatch == MismatchPolicy::Fail {
+                return Err(Error::VersionMismatch(mismatch));
+            }
+            warnings.push(mismatch);
+        }
+    }
+    let (version, release) = match latest {
+        Some(update) => (update.version.as_str(), update.release),
+        None => {
+            warnings.push(format!(
+                "{} has no history, assuming version 0 release 1",
+                sample.package.name
+            ));
+            ("0", 1)
+        }
+    };
+    // Unmapped components are carried over verbatim for review
+    let component = sample.package.part_of.as_deref().map(|part_of| {
+        options.component(part_of).map_or_else(
+            || {
+                warnings.push(format!(
+                    "{} is part of unmapped component {part_of}",
+                    sample.package.name
+                ));
+                part_of.to_string()
+            },
+            str::to_string,
+        )
+    });
+    let settings = match options
+        .overrides
+        .get(options.bucketing.key(&sample.package))
+    {
+        Some(overrides) => options.recipe.with(overrides),
+        None => options.recipe.clone(),
+    };
+    let recipe = Recipe {
+        name: source.clone(),
+        version: version.to_string(),
+        release: options.release.apply(release),
+        homepage,
+        upstreams,
+        summary: sample.package.summary.get(&options.language).to_string(),
+        description: block_text(sample.package.description.get(&options.language)),
+        strip: settings.strip.unwrap_or(false),
+        networking: settings.networking,
+        toolchain: settings.toolchain,
+        license: licenses.licenses,
+        component,
+        builddeps: match pspec {
+            Some(pspec) => pspec.build_deps().map(|d| options.rename(d)).collect(),
+            None => external_deps(&input)
+                .filter(|d| d.ends_with("-devel"))
+                .map(|d| options.rename(d))
+                .collect(),
+        },
+        rundeps: run_deps(sample, options),
+        conflicts: conflicts(sample, options),
+        packages,
+        emul32: !emul32.is_empty(),
+        setup: source_build.map(generate_setup_script),
+        build: source_build.map(|_| "%make\n".to_string()),
+        install: match source_build {
+            Some(_) => "%make_install\n".to_string(),
+            None => generate_install_script(&native, &base_uri, &names, options)?,
+        },
+        profiles,
+        ..Default::default()
+    };
+    if source_build.is_some() {
+        warnings.push(format!(
+            "{source} uses autotools build steps, port them from actions.py"
+        ));
+    }
+
+    let header = generate_header(&sample.package, options);
+    let attribution = generate_attribution(&sample.package);
+    if attribution.is_empty() {
+        warnings.push(format!("{} has no recorded packager", sample.package.name));
+    }
+    let replaces = generate_replaces(&input, options);
+    for pkg in input.iter().filter(|p| p.package.replaces.is_some()) {
+        warnings.push(format!(
+            "{} replaces other packages, which stone.yml cannot express",
+            pkg.package.name
+        ));
+    }
+    let origins = input
+        .iter()
+        .map(|pkg| origin(pkg, &base_uri, options))
+        .collect::<Result<Vec<_>, _>>()?;
+    let checksums = generate_checksums(&origins);
+    let mut yml = recipe.to_yaml()?;
+    let changelog = generate_changelog(&sample.package);
+    if let Some(template) = &options.template {
+        let comments = [
+            ("header", header.as_str()),
+            ("attribution", attribution.as_str()),
+            ("replaces", replaces.as_str()),
+            ("changelog", changelog.as_str()),
+            ("checksums", checksums.as_str()),
+        ];
+        yml = render(template, &recipe, &yml, &comments)?;
+    } else {
+        yml.insert_str(0, &(header + &attribution));
+        yml.push_str(&replaces);
+        if options.changelog {
+            yml.push_str(&changelog);
+        }
+        if options.checksums {
+            yml.push_str(&checksums);
+        }
+    }
+
+    let monitoring = generate_monitoring(&recipe.name, sample.package.source.homepage.as_deref());
+
+    let entry = ManifestEntry {
+        name: recipe.name.clone(),
+        version: recipe.version.clone(),
+        release: recipe.release,
+        packages: input
+            .iter()
+            .map(|p| options.rename(&p.package.name))
+            .collect(),
+        path: PathBuf::new(),
+    };
+
+    Ok(Converted {
+        recipe: yml,
+        warnings,
+        monitoring,
+        origins,
+        entry,
+        relocated: if options.usr_merge {
+            relocations(&input)
+        } else {
+            vec![]
+        },
+    })
+}
+
+/// A recipe merged into an existing one, along with anything that needs
+/// manual review
+#[derive(Debug, Clone, Default)]
+pub struct Merged {
+    pub recipe: String,
+    /// Kept install steps, profiles or emul32 that differ from the
+    /// generated ones
+    pub warnings: Vec<String>,
+}
+
+/// Update the fields of an `existing` recipe that follow from the eopkgs,
+/// `REGENERATED`, from a freshly `generated` one, leaving manual edits to
+/// other fields alone.
+///
+/// The recipes are spliced as text, key by key, so the rest of the
+/// existing recipe including its comments is kept exactly as written. The
+/// provenance header is replaced by the generated one. Install steps are
+/// often edited by hand, so they are kept too, with a warning when they no
+/// longer match the generated ones. Merges that don't yield the generated
+/// fields are rejected.
+pub fn merge(existing: &str, generated: &str) -> Result<Merged, Error> {
+    let replacements = blocks(generated)
+        .into_iter()
+        .filter_map(|(key, text)| Some((key?, text)))
+        .filter(|(key, _)| REGENERATED.contains(key))
+        .collect::<BTreeMap<_, _>>();
+
+    let header = generated
+        .split_inclusive('\n')
+        .take_while(|l| is_header(l))
+        .collect::<String>();
+    let comments = existing
+        .split_inclusive('\n')
+        .take_while(|l| l.starts_with('#'))
+        .collect::<String>();
+    let mut merged = header;
+    merged.extend(comments.split_inclusive('\n').filter(|l| !is_header(l)));
+
+    let mut replaced = BTreeSet::new();
+    for (key, text) in blocks(&existing[comments.len()..]) {
+        match key.filter(|key| REGENERATED.contains(key)) {
+            // Keys no longer generated, such as a dropped profile, go away
+            Some(key) => {
+                replaced.insert(key);
+                merged.extend(replacements.get(key).copied());
+            }
+            None => merged.push_str(text),
+        }
+    }
+    for (key, text) in replacements.iter() {
+        if !replaced.contains(key) {
+            merged.push_str(text);
+        }
+    }
+
+    let recipe = Recipe::parse(&merged)?;
+    let (existing, generated) = (Recipe::parse(existing)?, Recipe::parse(generated)?);
+    if (&recipe.version, recipe.release, &recipe.upstreams)
+        != (&generated.version, generated.release, &generated.upstreams)
+        || (recipe.emul32, &recipe.install, &recipe.profiles)
+            != (existing.emul32, &existing.install, &existing.profiles)
+    {
+        return Err(Error::Merge(recipe.name));
+    }
+
+    let mut warnings = vec![];
+    let kept = [
+        ("emul32", recipe.emul32 != generated.emul32),
+        ("install", recipe.install != generated.install),
+        ("profiles", recipe.profiles != generated.profiles),
     ];
+    for (key, _) in kept.into_iter().filter(|(_, differs)| *differs) {
+        warnings.push(format!(
+            "{} keeps its existing {key} rather than the generated one",
+            recipe.name
+        ));
+    }
+
+    Ok(Merged {
+        recipe: merged,
+        warnings,
+    })
+}
+
+/// Split a recipe into its top-level keys, each with the lines nested
+/// under it. Lines outside of any key, such as comments between keys or
+/// the blank lines after one, are returned without a key.
+fn blocks(yaml: &str) -> Vec<(Option<&str>, &str)> {
+    let mut blocks: Vec<(Option<&str>, Range<usize>)> = vec![];
+    let mut offset = 0;
+    for line in yaml.split_inclusive('\n') {
+        let span = offset..offset + line.len();
+        offset = span.end;
+
+        let key = line
+            .split_once(':')
+            .map(|(key, _)| key)
+            .filter(|key| !key.starts_with([' ', '\t', '#', '-']) && !key.is_empty());
+        let nested = line.starts_with([' ', '\t', '-']) || line.trim().is_empty();
+        match blocks.last_mut() {
+            Some((last, range)) if key.is_none() && (nested || last.is_none()) => {
+                range.end = span.end
+            }
+            _ => blocks.push((key, span)),
+        }
+    }
+
+    // Blank lines after a key separate it from the next rather than belong to it
+    let mut split = vec![];
+    for (key, range) in blocks {
+        let text = &yaml[range];
+        let content = text.trim_end().len();
+        let end = text[content..]
+            .find('\n')
+            .map_or(text.len(), |n| content + n + 1);
+        let (body, blank) = text.split_at(end);
+        split.push((key, body));
+        if !blank.is_empty() {
+            split.push((None, blank));
+        }
+    }
+    split
+}
+
+/// Provenance of a single input package
+fn origin(pkg: &HashedPackage, base_uri: &Url, options: &Options) -> Result<Origin, Error> {
+    let latest = pkg.package.history.latest();
+    Ok(Origin {
+        name: pkg.package.name.to_string(),
+        source: pkg.package.source.name.to_string(),
+        version: latest.map(|u| u.version.clone()),
+        release: latest.map(|u| u.release),
+        uri: pkg.package.uri(base_uri)?.to_string(),
+        package_hash: pkg.package.package_hash.clone(),
+        sha256: const_hex::encode(pkg.hash),
+        payload_sha256: repacked(pkg, options).map(|(_, payload)| const_hex::encode(payload.hash)),
+    })
+}
+
+/// Release feed of projects hosted on a forge with a predictable one
+fn release_feed(homepage: &str) -> Option<String> {
+    let url = Url::parse(homepage).ok()?;
+    let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+    let (owner, project) = (segments.next()?, segments.next()?);
+    let project = project.trim_end_matches(".git");
+    match url.host_str()? {
+        "github.com" => Some(format!(
+            "https://github.com/{owner}/{project}/releases.atom"
+        )),
+        "gitlab.com" => Some(format!(
+            "https://gitlab.com/{owner}/{project}/-/tags?format=atom"
+        )),
+        _ => None,
+    }
+}
+
+/// Stub `monitoring.yaml`, leaving the release-monitoring.org ID and CPE
+/// as placeholders to be looked up
+fn generate_monitoring(name: &str, homepage: Option<&str>) -> String {
+    let rss = homepage
+        .and_then(release_feed)
+        .unwrap_or_else(|| "~".to_string());
+    format!(
+        "releases:\n  id: ~ # https://release-monitoring.org/projects/search/?pattern={name}\n  rss: {rss}\n\
+         # No known CPE, checked with https://nvd.nist.gov/products/cpe/search\n\
+         security:\n  cpe: ~\n"
+    )
+}
+
+/// Render a user template with the fields of the recipe as context, along
+/// with the default `yaml` layout and the generated `comments`, such as the
+/// `changelog`
+fn render(
+    template: &str,
+    recipe: &Recipe,
+    yaml: &str,
+    comments: &[(&str, &str)],
+) -> Result<String, Error> {
+    let mut context = tera::Context::from_serialize(recipe)?;
+    context.insert("yaml", yaml);
+    for (name, comment) in comments {
+        context.insert(*name, comment);
+    }
+    Ok(tera::Tera::one_off(template, &context, false)?)
+}
+
+/// Sorted runtime dependencies of a single package, renamed
+fn run_deps(pkg: &HashedPackage, options: &Options) -> Vec<String> {
+    pkg.package
+        .run_deps
+        .iter()
+        .flat_map(|r| r.deps.iter())
+        .filter(|d| d.value != pkg.package.name)
+        .map(|d| options.rename(&d.value))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Sorted packages a single package conflicts with, renamed
+fn conflicts(pkg: &HashedPackage, options: &Options) -> Vec<String> {
+    pkg.package
+        .conflicts
+        .iter()
+        .flat_map(|c| c.packages.iter())
+        .map(|name| options.rename(name))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// TODO comments for the packages replaced by the bucket, which have no
+/// stone.yml equivalent
+fn generate_replaces(input: &[&HashedPackage], options: &Options) -> String {
+    let mut lines = vec![];
+    for pkg in input.iter() {
+        let Some(replaces) = &pkg.package.replaces else {
+            continue;
+        };
+        let replaced = replaces
+            .packages
+            .iter()
+            .map(|name| options.rename(name))
+            .collect::<Vec<_>>();
+        lines.push(format!(
+            "# TODO: {} replaces {}",
+            options.rename(&pkg.package.name),
+            replaced.join(", ")
+        ));
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    lines.insert(0, String::new());
+    lines.join("\n") + "\n"
+}
 
-    Ok(yml.join("\n"))
+/// Path patterns covering the files of a package, collapsing the well
+/// known locations
+///
+/// Empty directories are declared too, as nothing else would claim them
+fn patterns(files: &Files, usr_merge: bool) -> Vec<String> {
+    files
+        .files
+        .iter()
+        .filter(|f| !f.is_directory())
+        .chain(files.empty_directories())
+        .map(|f| {
+            let path = format!("/{}", f.path.trim_start_matches('/'));
+            let path = if usr_merge {
+                usr_merged(&path).unwrap_or(path)
+            } else {
+                path
+            };
+            LOCATIONS
+                .iter()
+                .find(|l| {
+                    path.strip_prefix(*l)
+                        .is_some_and(|rest| rest.starts_with('/'))
+                })
+                .map_or(path.clone(), |l| l.to_string())
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
 }
 
-fn generate_install_script(input: &[&HashedPackage], base_uri: &Url) -> Result<String, Error> {
-    let mut zips = vec![];
+/// Commands restoring what the payload alone may not carry over: the
+/// setuid and setgid bits, and empty directories
+fn special_files(files: &Files) -> Vec<String> {
+    let setuid = files
+        .files
+        .iter()
+        .filter(|f| f.is_setuid())
+        .filter_map(|f| {
+            let path = f.path.trim_start_matches('/');
+            Some(format!(
+                "chmod {:o} %(installroot)/{path}",
+                f.permissions()?
+            ))
+        });
+    let empty = files.empty_directories().map(|f| {
+        let path = f.path.trim_start_matches('/');
+        match f.permissions() {
+            Some(mode) => format!("install -dm{mode:o} %(installroot)/{path}"),
+            None => format!("install -d %(installroot)/{path}"),
+        }
+    });
+    setuid.chain(empty).collect()
+}
+
+/// Runtime dependencies of the bucket on packages outside of it, sorted.
+/// Without a pspec the `-devel` ones are a best-effort guess at the build
+/// dependencies of the source.
+fn external_deps<'a>(input: &[&'a HashedPackage]) -> impl Iterator<Item = &'a str> {
+    let ours = input
+        .iter()
+        .map(|p| p.package.name.as_str())
+        .collect::<BTreeSet<_>>();
+    input
+        .iter()
+        .filter_map(|p| p.package.run_deps.as_ref())
+        .flat_map(|r| r.deps.iter())
+        .map(|d| d.value.as_str())
+        .filter(|name| !ours.contains(name))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+}
+
+/// Tidy multi-line text so it serializes as a literal block scalar: the
+/// emitter falls back to quoting when lines carry indentation or trailing
+/// whitespace. Paragraph breaks are kept, runs of blank lines collapsed.
+fn block_text(text: &str) -> String {
+    let mut lines: Vec<&str> = vec![];
+    for line in text.trim().lines().map(str::trim) {
+        if !(line.is_empty() && lines.last().is_some_and(|l| l.is_empty())) {
+            lines.push(line);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Location of `path` on a usr-merged system, if it needs to move
+fn usr_merged(path: &str) -> Option<String> {
+    USR_MERGE.iter().find_map(|(from, to)| {
+        let rest = path.strip_prefix(from)?;
+        (rest.is_empty() || rest.starts_with('/')).then(|| format!("{to}{rest}"))
+    })
+}
+
+/// Legacy paths shipped by the bucket and where the usr-merge moves them
+fn relocations(input: &[&HashedPackage]) -> Vec<String> {
+    input
+        .iter()
+        .filter_map(|p| p.files.as_ref())
+        .flat_map(|f| f.files.iter())
+        .filter(|f| !f.is_directory())
+        .filter_map(|f| {
+            let path = format!("/{}", f.path.trim_start_matches('/'));
+            usr_merged(&path).map(|to| format!("{path} -> {to}"))
+        })
+        .collect()
+}
+
+/// Repacked payload of `pkg` and where it is published, when in use
+fn repacked<'a>(pkg: &'a HashedPackage, options: &'a Options) -> Option<(&'a Url, &'a Payload)> {
+    options.repack_uri.as_ref().zip(pkg.payload.as_ref())
+}
+
+/// Names of the archives within the source directory, keyed by their URI.
+/// Archives sharing a file name are told apart by a prefix of their hash.
+fn source_names(
+    input: &[&HashedPackage],
+    base_uri: &Url,
+) -> Result<BTreeMap<String, String>, Error> {
+    let mut archives = vec![];
     for pkg in input.iter() {
-        let url = base_uri.join(&pkg.package.package_uri)?;
-        let path = PathBuf::from(url.path());
-        let name = path.file_name().ok_or(Error::Path)?.to_string_lossy();
-        zips.push(format!("    unzip -o %(sourcedir)/{name}"));
-        zips.push("    tar xf install.tar.xz -C %(installroot)".to_string());
+        let uri = pkg.package.uri(base_uri)?;
+        let name = PathBuf::from(uri.path())
+            .file_name()
+            .ok_or(Error::Path)?
+            .to_string_lossy()
+            .to_string();
+        archives.push((uri.to_string(), name, const_hex::encode(pkg.hash)));
+    }
+
+    let mut seen = BTreeMap::<_, BTreeSet<_>>::new();
+    for (uri, name, _) in archives.iter() {
+        seen.entry(name.clone()).or_default().insert(uri.clone());
+    }
+    Ok(archives
+        .into_iter()
+        .map(|(uri, name, hash)| {
+            let name = if seen[&name].len() > 1 {
+                format!("{}-{name}", &hash[..8])
+            } else {
+                name
+            };
+            (uri, name)
+        })
+        .collect())
+}
+
+fn generate_install_script(
+    input: &[&HashedPackage],
+    base_uri: &Url,
+    names: &BTreeMap<String, String>,
+    options: &Options,
+) -> Result<String, Error> {
+    let mut lines = vec!["%install_dir %(installroot)".to_string()];
+    for pkg in input.iter() {
+        // Repacked payloads are unpacked by boulder next to the workdir
+        if repacked(pkg, options).is_some() {
+            let name = &pkg.package.name;
+            lines.push(format!("cp -a %(workdir)/../{name}/. %(installroot)/"));
+        } else {
+            let url = pkg.package.uri(base_uri)?;
+            let name = names.get(url.as_str()).ok_or(Error::Path)?;
+            lines.push(format!("unzip -o %(sourcedir)/{name}"));
+            lines.push("tar xf install.tar.xz -C %(installroot)".to_string());
+        }
+        lines.extend(pkg.files.iter().flat_map(special_files));
+    }
+    if options.usr_merge {
+        for (from, to) in USR_MERGE {
+            let (from, to) = (
+                format!("%(installroot){from}"),
+                format!("%(installroot){to}"),
+            );
+            lines.push(format!(
+                "if [ -d {from} ] && [ ! -L {from} ]; then mkdir -p {to} && cp -a {from}/. {to}/ && rm -rf {from}; fi"
+            ));
+        }
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Comment marking the recipe as generated, and from which snapshot and
+/// eopkg release
+fn generate_header(package: &Package, options: &Options) -> String {
+    let mut lines = vec![format!(
+        "# Generated by {} {}, review before building",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )];
+    if let Some(date) = options.snapshot {
+        lines.push(format!("# Index snapshot of {date}"));
+    }
+    if let Some(update) = package.history.latest() {
+        lines.push(format!(
+            "# From eopkg {} {}-{}",
+            package.name, update.version, update.release
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Whether `line` belongs to the header written by `generate_header`
+fn is_header(line: &str) -> bool {
+    let generated = format!("# Generated by {} ", env!("CARGO_PKG_NAME"));
+    line.starts_with(&generated)
+        || line.starts_with("# Index snapshot of ")
+        || line.starts_with("# From eopkg ")
+}
+
+/// Comment crediting the Solus packager of the source, empty when unknown
+fn generate_attribution(package: &Package) -> String {
+    match &package.source.packager {
+        Some(packager) if !packager.name.is_empty() => format!(
+            "# Converted from the Solus package maintained by {} <{}>\n",
+            packager.name, packager.email
+        ),
+        _ => String::new(),
+    }
+}
+
+/// Patches of the pspec applied in order, before configuring the source
+fn generate_setup_script(pspec: &Pspec) -> String {
+    let mut lines = vec![];
+    for patch in pspec.source.patches.iter().flat_map(|p| p.patches.iter()) {
+        let path = patch.path.trim();
+        lines.push(match patch.level.unwrap_or(1) {
+            1 => format!("%patch %(pkgdir)/{path}"),
+            level => format!("patch -f -N -p{level} -i %(pkgdir)/{path}"),
+        });
     }
+    lines.push("%configure".to_string());
+    lines.join("\n") + "\n"
+}
 
-    Ok(format!(
-        "    %install_dir %(installroot)\n{}",
-        zips.join("\n")
-    ))
+/// Published sha1 and downloaded sha256 of each eopkg as YAML comments,
+/// tying the upstreams back to the archives Solus shipped
+fn generate_checksums(origins: &[Origin]) -> String {
+    let mut lines = vec!["".to_string(), "# Checksums".to_string()];
+    for origin in origins {
+        lines.push(format!("# {}", origin.uri));
+        lines.push(format!("#   sha1: {}", origin.package_hash));
+        lines.push(format!("#   sha256: {}", origin.sha256));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// History of the package as YAML comments
+fn generate_changelog(package: &Package) -> String {
+    let mut lines = vec!["".to_string(), "# Changelog".to_string()];
+    for entry in package.history.changelog() {
+        lines.extend(entry.to_string().lines().map(|l| format!("# {l}")));
+    }
+    lines.join("\n") + "\n"
 }
 
 #[derive(Debug, Error)]
@@ -88,4 +1266,759 @@ pub enum Error {
 
     #[error("url: {0}")]
     Url(#[from] url::ParseError),
+
+    #[error("yaml: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("template: {0}")]
+    Template(#[from] tera::Error),
+
+    #[error("invalid bucketing: {0}, expected source or package")]
+    InvalidBucketing(String),
+
+    #[error("invalid release policy: {0}, expected keep, reset or an offset")]
+    InvalidReleasePolicy(String),
+
+    #[error("invalid mismatch policy: {0}, expected warn, highest or fail")]
+    InvalidMismatchPolicy(String),
+
+    #[error("version mismatch: {0}")]
+    VersionMismatch(String),
+
+    #[error("invalid layout: {0}, expected flat or recipes")]
+    InvalidLayout(String),
+
+    #[error("upstream archive {0} was not hashed")]
+    UnhashedArchive(String),
+
+    #[error("merging into the existing recipe of {0} lost generated or kept fields")]
+    Merge(String),
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use url::Url;
+
+    use super::{
+        convert, merge, Bucketing, Error, HashedPackage, Layout, Merged, MismatchPolicy, Options,
+        Payload, RecipeOptions, ReleasePolicy,
+    };
+    use crate::eopkg::{
+        files::Files,
+        index::{
+            Conflicts, Dependency, Package, Packager, Replaces, RuntimeDependencies, Source,
+            Translations, Update,
+        },
+        pspec::{self, Patch, Patches, Pspec},
+    };
+
+    #[test]
+    fn escaping() {
+        let package = HashedPackage {
+            hash: [0; 32],
+            files: None,
+            payload: None,
+            package: Package {
+                name: "nano".into(),
+                package_uri: "n/nano/nano-7.2-163-1-x86_64.eopkg".into(),
+                summary: Translations(
+                    [("en".into(), "Editor: small & \"friendly\"".into())].into(),
+                ),
+                description: Translations(
+                    [(
+                        "en".into(),
+                        "\n    First paragraph  \n    continued.\n\n\n    Second paragraph.\n  "
+                            .into(),
+                    )]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+        };
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&package], base, None, &Options::default()).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(
+            recipe["summary"].as_str(),
+            Some("Editor: small & \"friendly\"")
+        );
+        assert_eq!(recipe["version"].as_str(), Some("0"));
+        assert!(converted
+            .recipe
+            .contains("description: |\n  First paragraph\n  continued.\n\n  Second"));
+        assert_eq!(
+            recipe["description"].as_str(),
+            Some("First paragraph\ncontinued.\n\nSecond paragraph.\n")
+        );
+        assert!(recipe["upstreams"][0]
+            .get("https://cdn.getsol.us/repo/shannon/n/nano/nano-7.2-163-1-x86_64.eopkg")
+            .is_some());
+    }
+
+    /// Package of the `nano` source depending on `deps`
+    fn package(name: &str, deps: &[&str]) -> HashedPackage {
+        HashedPackage {
+            hash: [0; 32],
+            files: None,
+            payload: None,
+            package: Package {
+                name: name.into(),
+                package_uri: format!("n/nano/{name}-7.2-163-1-x86_64.eopkg"),
+                source: Source {
+                    name: "nano".into(),
+                    ..Default::default()
+                },
+                run_deps: Some(RuntimeDependencies {
+                    deps: deps
+                        .iter()
+                        .map(|d| Dependency {
+                            value: (*d).into(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                }),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn dependencies() {
+        let nano = package("nano", &["ncurses", "glibc"]);
+        let devel = package("nano-devel", &["nano", "ncurses-devel"]);
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano, &devel], base, None, &Options::default()).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        let list = |key: &str| serde_yaml::from_value::<Vec<String>>(recipe[key].clone()).unwrap();
+        assert_eq!(list("rundeps"), ["glibc", "ncurses"]);
+        assert_eq!(list("builddeps"), ["ncurses-devel"]);
+
+        let devel = &recipe["packages"][0]["%(name)-devel"];
+        assert_eq!(devel["rundeps"][0].as_str(), Some("nano"));
+        assert_eq!(devel["paths"][0].as_str(), Some("/usr/include"));
+    }
+
+    #[test]
+    fn patterns() {
+        let files: Files = quick_xml::de::from_str(
+            r#"<Files>
+                <File><Path>usr/include/zlib.h</Path><Type>header</Type></File>
+                <File><Path>usr/include/zconf.h</Path><Type>header</Type></File>
+                <File><Path>usr/lib64/libz.so</Path><Type>library</Type></File>
+                <File><Path>usr/lib64/pkgconfig/zlib.pc</Path><Type>data</Type></File>
+                <File><Path>usr/lib64/pkgconfig</Path><Type>data</Type><Mode>040755</Mode></File>
+            </Files>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            super::patterns(&files, false),
+            ["/usr/include", "/usr/lib64/libz.so", "/usr/lib64/pkgconfig"]
+        );
+        assert_eq!(
+            super::usr_merged("/lib64/libz.so.1").as_deref(),
+            Some("/usr/lib64/libz.so.1")
+        );
+        assert_eq!(
+            super::usr_merged("/sbin/ldconfig").as_deref(),
+            Some("/usr/bin/ldconfig")
+        );
+        assert_eq!(super::usr_merged("/library/x"), None);
+        assert_eq!(super::usr_merged("/usr/lib64/libz.so"), None);
+    }
+
+    #[test]
+    fn renames() {
+        let nano = package("nano", &["libgcrypt", "ncurses"]);
+        let devel = package("nano-devel", &["nano"]);
+        let options = Options {
+            renames: [
+                ("nano".into(), "gnu-nano".into()),
+                ("libgcrypt".into(), "gcrypt".into()),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano, &devel], base, None, &options).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["name"].as_str(), Some("gnu-nano"));
+        assert_eq!(recipe["rundeps"][0].as_str(), Some("gcrypt"));
+        let devel = &recipe["packages"][0]["nano-devel"];
+        assert_eq!(devel["rundeps"][0].as_str(), Some("gnu-nano"));
+    }
+
+    #[test]
+    fn emul32() {
+        let nano = package("nano", &[]);
+        let nano32 = package("nano-32bit", &[]);
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano, &nano32], base, None, &Options::default()).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["emul32"].as_bool(), Some(true));
+        let install = recipe["install"].as_str().unwrap();
+        assert!(install.contains("nano-7.2") && !install.contains("32bit"));
+        let profile = recipe["profiles"][0]["emul32/x86_64"]["install"].as_str();
+        assert!(profile.unwrap().contains("nano-32bit-7.2"));
+    }
+
+    #[test]
+    fn template() {
+        let nano = package("nano", &["ncurses"]);
+        let options = Options {
+            template: Some(
+                "# Maintained downstream\nname: {{ name }}\nrundeps: {{ rundeps | join(sep=\", \") }}\n".into(),
+            ),
+            ..Default::default()
+        };
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base, None, &options).unwrap();
+        assert_eq!(
+            converted.recipe,
+            "# Maintained downstream\nname: nano\nrundeps: ncurses\n"
+        );
+    }
+
+    #[test]
+    fn repack() {
+        let mut nano = package("nano", &[]);
+        nano.files = Some(
+            quick_xml::de::from_str(
+                r#"<Files>
+                    <File><Path>usr/bin/nano</Path><Type>executable</Type><Mode>0104755</Mode></File>
+                    <File><Path>var/lib/nano</Path><Type>data</Type><Mode>040700</Mode></File>
+                </Files>"#,
+            )
+            .unwrap(),
+        );
+        nano.payload = Some(Payload {
+            file_name: "nano-7.2-163-1-x86_64.tar.xz".into(),
+            hash: [1; 32],
+        });
+        let options = Options {
+            repack_uri: Some(Url::parse("https://example.com/payloads/").unwrap()),
+            ..Default::default()
+        };
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base, None, &options).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        let upstream =
+            &recipe["upstreams"][0]["https://example.com/payloads/nano-7.2-163-1-x86_64.tar.xz"];
+        assert_eq!(upstream["unpack"].as_bool(), Some(true));
+        assert_eq!(upstream["unpackdir"].as_str(), Some("nano"));
+        let install = recipe["install"].as_str().unwrap();
+        assert!(!install.contains("unzip") && install.contains("%(workdir)/../nano/."));
+        assert!(install.ends_with(
+            "chmod 4755 %(installroot)/usr/bin/nano\ninstall -dm700 %(installroot)/var/lib/nano\n"
+        ));
+    }
+
+    #[test]
+    fn deterministic() {
+        let nano = package("nano", &["ncurses", "glibc"]);
+        let devel = package("nano-devel", &["nano"]);
+        let docs = package("nano-docs", &[]);
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let recipe = |input| {
+            convert(input, base.clone(), None, &Options::default())
+                .unwrap()
+                .recipe
+        };
+        assert_eq!(
+            recipe(vec![&docs, &nano, &devel]),
+            recipe(vec![&nano, &devel, &docs])
+        );
+    }
+
+    #[test]
+    fn monitoring() {
+        let monitoring = super::generate_monitoring("zlib", Some("https://github.com/madler/zlib"));
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&monitoring).unwrap();
+        assert!(parsed["releases"]["id"].is_null());
+        assert_eq!(
+            parsed["releases"]["rss"].as_str(),
+            Some("https://github.com/madler/zlib/releases.atom")
+        );
+        assert!(parsed["security"]["cpe"].is_null());
+
+        let monitoring = super::generate_monitoring("nano", Some("https://www.nano-editor.org/"));
+        assert!(monitoring.contains("rss: ~"));
+    }
+
+    #[test]
+    fn attribution() {
+        let mut nano = package("nano", &[]);
+        nano.package.source.packager = Some(Packager {
+            name: "Joey Riches".into(),
+            email: "josephriches@gmail.com".into(),
+        });
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base, None, &Options::default()).unwrap();
+        assert!(converted.recipe.contains(
+            "\n# Converted from the Solus package maintained by Joey Riches <josephriches@gmail.com>\nname: nano\n"
+        ));
+        assert!(converted.warnings.iter().all(|w| !w.contains("packager")));
+    }
+
+    #[test]
+    fn header() {
+        let mut nano = package("nano", &[]);
+        nano.package.history.updates.push(Update {
+            release: 163,
+            version: "7.2".into(),
+            ..Default::default()
+        });
+        let options = Options {
+            snapshot: NaiveDate::from_ymd_opt(2023, 10, 14),
+            ..Default::default()
+        };
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base, None, &options).unwrap();
+        let header = converted.recipe.lines().take(3).collect::<Vec<_>>();
+        assert!(header[0].starts_with("# Generated by a-piece-of-pisi "));
+        assert_eq!(
+            header[1..],
+            [
+                "# Index snapshot of 2023-10-14",
+                "# From eopkg nano 7.2-163"
+            ]
+        );
+        assert!(converted
+            .warnings
+            .contains(&"nano release 163 has an unreadable date \"\"".to_string()));
+    }
+
+    #[test]
+    fn components() {
+        let options = Options {
+            components: [
+                ("system".into(), "system.core".into()),
+                ("system.devel".into(), "system.devel".into()),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        assert_eq!(options.component("system.devel"), Some("system.devel"));
+        assert_eq!(options.component("system.base"), Some("system.core"));
+        assert_eq!(options.component("desktop.kde"), None);
+
+        let mut nano = package("nano", &[]);
+        nano.package.part_of = Some("system.utils".into());
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base, None, &options).unwrap();
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["component"].as_str(), Some("system.core"));
+    }
+
+    #[test]
+    fn relations() {
+        let mut nano = package("nano", &[]);
+        nano.package.conflicts = Some(Conflicts {
+            packages: vec!["pico".into()],
+        });
+        nano.package.replaces = Some(Replaces {
+            packages: vec!["nano-tiny".into(), "pico".into()],
+        });
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base, None, &Options::default()).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["conflicts"][0].as_str(), Some("pico"));
+        assert!(converted
+            .recipe
+            .ends_with("\n# TODO: nano replaces nano-tiny, pico\n"));
+        assert!(converted.warnings.iter().any(|w| w.contains("replaces")));
+    }
+
+    #[test]
+    fn release() {
+        let policy = |s: &str| s.parse::<ReleasePolicy>().unwrap();
+        assert_eq!(policy("keep").apply(163), 163);
+        assert_eq!(policy("reset").apply(163), 1);
+        assert_eq!(policy("+2").apply(163), 165);
+        assert_eq!(policy("-200").apply(163), 1);
+        assert!("latest".parse::<ReleasePolicy>().is_err());
+    }
+
+    #[test]
+    fn provenance() {
+        let mut nano = package("nano", &[]);
+        nano.hash = [2; 32];
+        nano.package.package_hash = "9f1133ca8914518dcbd4ecf77910129c3df0ec08".into();
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base, None, &Options::default()).unwrap();
+
+        let origin = &converted.origins[0];
+        assert_eq!(
+            origin.uri,
+            "https://cdn.getsol.us/repo/shannon/n/nano/nano-7.2-163-1-x86_64.eopkg"
+        );
+        assert_eq!(
+            origin.package_hash,
+            "9f1133ca8914518dcbd4ecf77910129c3df0ec08"
+        );
+        assert_eq!(origin.sha256, "02".repeat(32));
+        assert!(origin.payload_sha256.is_none());
+        assert!(!converted.recipe.contains("# Checksums"));
+
+        let options = Options {
+            checksums: true,
+            ..Default::default()
+        };
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base, None, &options).unwrap();
+        assert!(converted.recipe.ends_with(&format!(
+            "# Checksums\n\
+            # https://cdn.getsol.us/repo/shannon/n/nano/nano-7.2-163-1-x86_64.eopkg\n\
+            #   sha1: 9f1133ca8914518dcbd4ecf77910129c3df0ec08\n\
+            #   sha256: {}\n",
+            "02".repeat(32)
+        )));
+    }
+
+    #[test]
+    fn source_mode() {
+        let nano = package("nano", &["ncurses"]);
+        let pspec = Pspec {
+            source: pspec::Source {
+                name: "nano".into(),
+                archives: vec![pspec::Archive {
+                    uri: "https://www.nano-editor.org/dist/v7/nano-7.2.tar.xz".into(),
+                    sha256: Some([3; 32]),
+                    ..Default::default()
+                }],
+                patches: Some(Patches {
+                    patches: vec![
+                        Patch {
+                            level: Some(1),
+                            path: "stateless.patch".into(),
+                        },
+                        Patch {
+                            level: Some(0),
+                            path: "fix-build.patch".into(),
+                        },
+                    ],
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let options = Options {
+            source: true,
+            ..Default::default()
+        };
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base.clone(), Some(&pspec), &options).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        let upstream =
+            &recipe["upstreams"][0]["https://www.nano-editor.org/dist/v7/nano-7.2.tar.xz"];
+        assert_eq!(upstream["unpack"].as_bool(), Some(true));
+        assert_eq!(upstream["hash"].as_str(), Some("03".repeat(32).as_str()));
+        assert_eq!(recipe["upstreams"].as_sequence().unwrap().len(), 1);
+        assert_eq!(
+            recipe["setup"].as_str(),
+            Some("%patch %(pkgdir)/stateless.patch\npatch -f -N -p0 -i %(pkgdir)/fix-build.patch\n%configure\n")
+        );
+        assert_eq!(recipe["install"].as_str(), Some("%make_install\n"));
+
+        // Binaries are repackaged when the pspec is unknown
+        let converted = convert(vec![&nano], base, None, &options).unwrap();
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert!(recipe["setup"].is_null());
+        assert!(converted.warnings[0].contains("repackaging"));
+    }
+
+    #[test]
+    fn merging() {
+        let existing = "# Generated by a-piece-of-pisi 0.0.1, review before building\n# From eopkg nano 7.1-1\n# Reviewed\nname: nano\nversion: 7.1\nrelease: 1\nupstreams:\n- https://example.com/nano-7.1.eopkg:\n    unpack: false\n    hash: '00'\n\n# Wide character support needs the w variant\nbuilddeps:\n- pkgconfig(ncursesw) # not ncurses\nemul32: true\ninstall: |\n  unzip -o %(sourcedir)/nano-7.1.eopkg\n\n  tar xf install.tar.xz -C %(installroot)\nprofiles:\n- emul32/x86_64:\n    install: |\n      unzip -o %(sourcedir)/nano-32bit-7.1.eopkg\n";
+        let generated = "# Generated by a-piece-of-pisi 0.1.0, review before building\n# From eopkg nano 7.2-2\nname: nano\nversion: '7.2'\nrelease: 2\nupstreams:\n- https://example.com/nano-7.2.eopkg:\n    unpack: false\n    hash: '01'\nbuilddeps:\n- ncurses-devel\ninstall: |\n  unzip -o %(sourcedir)/nano-7.2.eopkg\n";
+        let Merged { recipe, warnings } = merge(existing, generated).unwrap();
+        assert!(recipe.starts_with(
+            "# Generated by a-piece-of-pisi 0.1.0, review before building\n# From eopkg nano 7.2-2\n# Reviewed\nname: nano\n"
+        ));
+        assert!(recipe.contains(
+            "    hash: '01'\n\n# Wide character support needs the w variant\nbuilddeps:\n- pkgconfig(ncursesw) # not ncurses\n"
+        ));
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&recipe).unwrap();
+        assert_eq!(recipe["version"].as_str(), Some("7.2"));
+        assert_eq!(recipe["release"].as_u64(), Some(2));
+        assert!(recipe["upstreams"][0]
+            .get("https://example.com/nano-7.2.eopkg")
+            .is_some());
+        assert_eq!(recipe["builddeps"][0].as_str(), Some("pkgconfig(ncursesw)"));
+
+        // Install steps may have been edited by hand, so they stay
+        assert_eq!(
+            recipe["install"].as_str(),
+            Some(
+                "unzip -o %(sourcedir)/nano-7.1.eopkg\n\ntar xf install.tar.xz -C %(installroot)\n"
+            )
+        );
+        assert_eq!(recipe["emul32"].as_bool(), Some(true));
+        assert!(recipe["profiles"][0].get("emul32/x86_64").is_some());
+        assert_eq!(
+            warnings,
+            [
+                "nano keeps its existing emul32 rather than the generated one",
+                "nano keeps its existing install rather than the generated one",
+                "nano keeps its existing profiles rather than the generated one",
+            ]
+        );
+        let regenerated = merge(existing, existing).unwrap();
+        assert!(regenerated.warnings.is_empty());
+
+        // Keys the splice can't find are reported rather than duplicated
+        let quoted = existing.replace("version:", "\"version\":");
+        assert!(matches!(merge(&quoted, generated), Err(Error::Yaml(_))));
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(Layout::Flat.path("nano").to_str(), Some("nano"));
+        assert_eq!(
+            "recipes".parse::<Layout>().unwrap().path("Mesa").to_str(),
+            Some("m/Mesa")
+        );
+        assert!("tree".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn recipe_options() {
+        let options = Options {
+            recipe: RecipeOptions {
+                toolchain: Some("llvm".into()),
+                ..Default::default()
+            },
+            overrides: [(
+                "nano".into(),
+                RecipeOptions {
+                    strip: Some(true),
+                    toolchain: Some("gnu".into()),
+                    ..Default::default()
+                },
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let nano = package("nano", &[]);
+        let converted = convert(vec![&nano], base.clone(), None, &options).unwrap();
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["strip"].as_bool(), Some(true));
+        assert_eq!(recipe["toolchain"].as_str(), Some("gnu"));
+        assert!(recipe.get("networking").is_none());
+
+        let mut zlib = package("zlib", &[]);
+        zlib.package.source.name = "zlib".into();
+        let converted = convert(vec![&zlib], base, None, &options).unwrap();
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["strip"].as_bool(), Some(false));
+        assert_eq!(recipe["toolchain"].as_str(), Some("llvm"));
+    }
+
+    #[test]
+    fn mismatch() {
+        let update = |version: &str, release| Update {
+            version: version.into(),
+            release,
+            ..Default::default()
+        };
+        let mut nano = package("nano", &[]);
+        nano.package.history.updates.push(update("7.1", 162));
+        let mut devel = package("nano-devel", &[]);
+        devel.package.history.updates.push(update("7.2", 163));
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+
+        let converted =
+            convert(vec![&nano, &devel], base.clone(), None, &Options::default()).unwrap();
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["release"].as_u64(), Some(162));
+        assert!(converted
+            .warnings
+            .contains(&"nano-devel is at 7.2-163, the recipe at 7.1-162".to_string()));
+
+        let options = Options {
+            mismatch: MismatchPolicy::Highest,
+            ..Default::default()
+        };
+        let converted = convert(vec![&nano, &devel], base.clone(), None, &options).unwrap();
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["version"].as_str(), Some("7.2"));
+
+        let options = Options {
+            mismatch: "fail".parse().unwrap(),
+            ..Default::default()
+        };
+        let err = convert(vec![&nano, &devel], base, None, &options).unwrap_err();
+        assert!(matches!(err, Error::VersionMismatch(_)));
+    }
+
+    #[test]
+    fn duplicates() {
+        let nano = package("nano", &[]);
+        let again = package("nano", &[]);
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano, &again], base, None, &Options::default()).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["upstreams"].as_sequence().unwrap().len(), 1);
+        assert_eq!(
+            recipe["install"].as_str().unwrap().matches("unzip").count(),
+            1
+        );
+        assert!(converted
+            .warnings
+            .contains(&"dropped 1 duplicate packages".to_string()));
+    }
+
+    #[test]
+    fn subpackage_metadata() {
+        let text = |text: &str| Translations([("en".into(), text.into())].into());
+        let mut nano = package("nano", &[]);
+        nano.package.summary = text("Small, friendly text editor");
+        nano.package.description = text("GNU nano is a small and friendly text editor.");
+        let mut devel = package("nano-devel", &[]);
+        devel.package.summary = text("Development files for nano");
+        devel.package.description = nano.package.description.clone();
+        let mut docs = package("nano-docs", &[]);
+        docs.package.description = text("Documentation for nano");
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted =
+            convert(vec![&nano, &devel, &docs], base, None, &Options::default()).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        let devel = &recipe["packages"][0]["%(name)-devel"];
+        assert_eq!(
+            devel["summary"].as_str(),
+            Some("Development files for nano")
+        );
+        assert!(devel.get("description").is_none());
+        let docs = &recipe["packages"][1]["%(name)-docs"];
+        assert_eq!(
+            docs["summary"].as_str(),
+            Some("Small, friendly text editor")
+        );
+        assert_eq!(
+            docs["description"].as_str(),
+            Some("Documentation for nano\n")
+        );
+    }
+
+    #[test]
+    fn special_files() {
+        let files: Files = quick_xml::de::from_str(
+            r#"<Files>
+                <File><Path>etc/sudoers</Path><Type>config</Type><Mode>0100440</Mode></File>
+                <File><Path>usr/bin/sudo</Path><Type>executable</Type><Mode>0104755</Mode></File>
+                <File><Path>var/db/sudo</Path><Type>data</Type><Mode>040700</Mode></File>
+            </Files>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            super::special_files(&files),
+            [
+                "chmod 4755 %(installroot)/usr/bin/sudo",
+                "install -dm700 %(installroot)/var/db/sudo"
+            ]
+        );
+        assert_eq!(
+            super::patterns(&files, false),
+            ["/etc/sudoers", "/usr/bin/sudo", "/var/db/sudo"]
+        );
+
+        let mut sudo = package("sudo", &[]);
+        sudo.package.source.name = "sudo".into();
+        sudo.files = Some(files);
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&sudo], base, None, &Options::default()).unwrap();
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert!(recipe["install"].as_str().unwrap().contains("chmod 4755"));
+        assert!(converted
+            .warnings
+            .iter()
+            .any(|w| w.contains("/etc/sudoers")));
+    }
+
+    #[test]
+    fn usr_merge() {
+        let files: Files = quick_xml::de::from_str(
+            r#"<Files>
+                <File><Path>bin/nano</Path><Type>executable</Type></File>
+                <File><Path>lib64</Path><Type>library</Type><Mode>040755</Mode></File>
+                <File><Path>usr/share/nano/nanorc</Path><Type>data</Type></File>
+            </Files>"#,
+        )
+        .unwrap();
+        let mut nano = package("nano", &[]);
+        nano.files = Some(files);
+        let options = Options {
+            usr_merge: true,
+            ..Default::default()
+        };
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano], base.clone(), None, &options).unwrap();
+        assert_eq!(converted.relocated, ["/bin/nano -> /usr/bin/nano"]);
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        let install = recipe["install"].as_str().unwrap();
+        assert!(install.contains(
+            "if [ -d %(installroot)/bin ] && [ ! -L %(installroot)/bin ]; then mkdir -p %(installroot)/usr/bin && cp -a %(installroot)/bin/. %(installroot)/usr/bin/ && rm -rf %(installroot)/bin; fi\n"
+        ));
+
+        let converted = convert(vec![&nano], base, None, &Options::default()).unwrap();
+        assert!(converted.relocated.is_empty());
+        assert!(!converted.recipe.contains("rm -rf"));
+    }
+
+    #[test]
+    fn bucketing() {
+        let devel = package("nano-devel", &["nano"]);
+        assert_eq!(Bucketing::Source.key(&devel.package), "nano");
+        assert_eq!(Bucketing::Package.key(&devel.package), "nano-devel");
+        assert_eq!("package".parse::<Bucketing>().unwrap(), Bucketing::Package);
+        assert!("binary".parse::<Bucketing>().is_err());
+
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&devel], base.clone(), None, &Options::default()).unwrap();
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["name"].as_str(), Some("nano"));
+
+        let options = Options {
+            bucketing: Bucketing::Package,
+            ..Default::default()
+        };
+        let converted = convert(vec![&devel], base, None, &options).unwrap();
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        assert_eq!(recipe["name"].as_str(), Some("nano-devel"));
+        assert_eq!(recipe["rundeps"][0].as_str(), Some("nano"));
+    }
+
+    #[test]
+    fn collisions() {
+        let nano = package("nano", &[]);
+        let mut devel = package("nano-devel", &[]);
+        devel.hash = [0xab; 32];
+        devel.package.repository = Some(Url::parse("https://example.com/repo/").unwrap());
+        devel.package.package_uri = nano.package.package_uri.clone();
+        let base = Url::parse("https://cdn.getsol.us/repo/shannon/").unwrap();
+        let converted = convert(vec![&nano, &devel], base, None, &Options::default()).unwrap();
+
+        let recipe: serde_yaml::Value = serde_yaml::from_str(&converted.recipe).unwrap();
+        let devel =
+            &recipe["upstreams"][1]["https://example.com/repo/n/nano/nano-7.2-163-1-x86_64.eopkg"];
+        assert_eq!(
+            devel["rename"].as_str(),
+            Some("abababab-nano-7.2-163-1-x86_64.eopkg")
+        );
+        let install = recipe["install"].as_str().unwrap();
+        assert!(install.contains("unzip -o %(sourcedir)/00000000-nano-7.2-163-1-x86_64.eopkg\n"));
+        assert!(install.contains("unzip -o %(sourcedir)/abababab-nano-7.2-163-1-x86_64.eopkg\n"));
+    }
 }
@@ -0,0 +1,106 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Structured changelogs built from package history
+
+use std::{cmp::Reverse, fmt};
+
+use chrono::NaiveDate;
+
+use super::index::{History, Packager};
+
+/// A single release in the changelog of a package
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub version: String,
+    pub release: u64,
+    /// Left out when the index records a date that can't be read
+    pub date: Option<NaiveDate>,
+    /// Packager responsible for the update, when recorded
+    pub author: Option<Packager>,
+    pub comment: String,
+    pub security: bool,
+}
+
+impl History {
+    /// Changelog of the package, newest release first
+    pub fn changelog(&self) -> Vec<Entry> {
+        let mut entries = self
+            .updates
+            .iter()
+            .map(|u| Entry {
+                version: u.version.clone(),
+                release: u.release,
+                date: u.date(),
+                author: (!u.name.is_empty()).then(|| Packager {
+                    name: u.name.clone(),
+                    email: u.email.clone(),
+                }),
+                comment: u.comment.trim().to_string(),
+                security: u.is_security(),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|e| Reverse(e.release));
+        entries
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.version, self.release)?;
+        if let Some(date) = &self.date {
+            write!(f, " ({date})")?;
+        }
+        if let Some(author) = &self.author {
+            write!(f, " {} <{}>", author.name, author.email)?;
+        }
+        if self.security {
+            f.write_str(" [security]")?;
+        }
+        for line in self.comment.lines() {
+            write!(f, "\n    {}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use quick_xml::de::from_str;
+
+    use crate::eopkg::index::History;
+
+    #[test]
+    fn changelog() {
+        let history: History = from_str(
+            r#"<History>
+                <Update release="25">
+                    <Date>2023-08-20</Date>
+                    <Version>1.3</Version>
+                    <Comment>Update to 1.3</Comment>
+                    <Name>Joey Riches</Name>
+                    <Email>josephriches@gmail.com</Email>
+                </Update>
+                <Update release="26" type="security">
+                    <Date>2023-10-14</Date>
+                    <Version>1.3</Version>
+                    <Comment>Fix CVE-2023-45853
+Rebuild with minizip disabled</Comment>
+                    <Name>Reilly Brogan</Name>
+                    <Email>reilly@reillybrogan.com</Email>
+                </Update>
+            </History>"#,
+        )
+        .unwrap();
+
+        let changelog = history.changelog();
+        assert_eq!(changelog[0].release, 26);
+        assert!(changelog[0].security && !changelog[1].security);
+        assert_eq!(
+            changelog[0].to_string(),
+            "1.3-26 (2023-10-14) Reilly Brogan <reilly@reillybrogan.com> [security]\n    \
+             Fix CVE-2023-45853\n    Rebuild with minizip disabled"
+        );
+    }
+}
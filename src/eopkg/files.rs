@@ -0,0 +1,145 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! files.xml parsing
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+/// File type bits of `st_mode`
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+const S_ISUID: u32 = 0o4000;
+const S_ISGID: u32 = 0o2000;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct File {
+    /// Path relative to the root, without a leading slash
+    pub path: String,
+    /// Classification such as `executable`, `library` or `config`
+    pub r#type: String,
+    pub size: Option<u64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Octal `st_mode`, including the file type bits
+    pub mode: Option<String>,
+    /// sha1sum of the contents, absent for directories
+    pub hash: Option<String>,
+    pub permanent: Option<bool>,
+}
+
+impl File {
+    /// Parsed `st_mode` bits
+    pub fn mode(&self) -> Option<u32> {
+        self.mode
+            .as_deref()
+            .and_then(|m| u32::from_str_radix(m.trim(), 8).ok())
+    }
+
+    pub fn is_config(&self) -> bool {
+        self.r#type == "config"
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.mode().is_some_and(|m| m & S_IFMT == S_IFDIR)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode().is_some_and(|m| m & S_IFMT == S_IFLNK)
+    }
+
+    /// Whether the file is setuid or setgid
+    pub fn is_setuid(&self) -> bool {
+        self.mode().is_some_and(|m| m & (S_ISUID | S_ISGID) != 0)
+    }
+
+    /// Permission bits, without the file type
+    pub fn permissions(&self) -> Option<u32> {
+        self.mode().map(|m| m & !S_IFMT)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Files {
+    #[serde(rename = "File", default)]
+    pub files: Vec<File>,
+}
+
+impl Files {
+    /// Directories nothing else in the package is shipped beneath
+    pub fn empty_directories(&self) -> impl Iterator<Item = &File> {
+        let paths = self
+            .files
+            .iter()
+            .map(|f| f.path.trim_matches('/'))
+            .collect::<BTreeSet<_>>();
+        self.files.iter().filter(move |f| {
+            let dir = format!("{}/", f.path.trim_matches('/'));
+            f.is_directory()
+                && !paths
+                    .range(dir.as_str()..)
+                    .next()
+                    .is_some_and(|p| p.starts_with(&dir))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use quick_xml::de::from_str;
+
+    use super::Files;
+
+    #[test]
+    fn parse_files() {
+        let doc: Files = from_str(
+            r#"<Files>
+                <File>
+                    <Path>etc/sudoers</Path>
+                    <Type>config</Type>
+                    <Size>4328</Size>
+                    <Uid>0</Uid>
+                    <Gid>0</Gid>
+                    <Mode>0100440</Mode>
+                    <Hash>b6e5c3ec0ae5e1880a4d7e9e1df3c1e6d1d1b8a0</Hash>
+                </File>
+                <File>
+                    <Path>usr/bin/sudo</Path>
+                    <Type>executable</Type>
+                    <Size>232416</Size>
+                    <Uid>0</Uid>
+                    <Gid>0</Gid>
+                    <Mode>0104755</Mode>
+                    <Hash>0f3cb2ad7c5b0e1e2f18d1e0d8d1c1a6f2b3e4d5</Hash>
+                </File>
+                <File>
+                    <Path>var/db/sudo</Path>
+                    <Type>data</Type>
+                    <Uid>0</Uid>
+                    <Gid>0</Gid>
+                    <Mode>040700</Mode>
+                </File>
+            </Files>"#,
+        )
+        .unwrap();
+
+        let [sudoers, sudo, db] = &doc.files[..] else {
+            panic!("expected 3 files");
+        };
+        assert!(sudoers.is_config() && !sudoers.is_setuid());
+        assert!(sudo.is_setuid() && !sudo.is_directory());
+        assert!(db.is_directory() && db.hash.is_none());
+        assert_eq!(db.mode(), Some(0o40700));
+        assert_eq!(db.permissions(), Some(0o700));
+        assert_eq!(
+            doc.empty_directories()
+                .map(|f| f.path.as_str())
+                .collect::<Vec<_>>(),
+            ["var/db/sudo"]
+        );
+    }
+}
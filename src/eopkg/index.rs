@@ -4,99 +4,913 @@
 
 //! eopkg index parsing
 
-use serde::Deserialize;
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fmt,
+    io::{BufRead, BufReader, Read, Write},
+    iter::FusedIterator,
+    path::Path,
+    str::FromStr,
+};
 
-#[derive(Clone, Debug, Default, Deserialize)]
+use chrono::NaiveDate;
+use lzma::LzmaReader;
+use quick_xml::events::{BytesStart, Event};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use super::name::{PackageName, SourceName};
+
+/// Language used when a translation is missing
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// A single translation of a field
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Text {
+    /// The distribution header omits the `xml` prefix
+    #[serde(rename = "@xml:lang", alias = "@lang", default)]
+    pub lang: String,
+    #[serde(rename = "$text", default)]
+    pub value: String,
+}
+
+/// Translations of a field keyed by language
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "Vec<Text>", into = "Vec<Text>")]
+pub struct Translations(pub BTreeMap<String, String>);
+
+impl Translations {
+    /// Text in `lang`, falling back to English and then to any language
+    pub fn get(&self, lang: &str) -> &str {
+        self.0
+            .get(lang)
+            .or_else(|| self.0.get(DEFAULT_LANGUAGE))
+            .or_else(|| self.0.values().next())
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+}
+
+impl From<Vec<Text>> for Translations {
+    fn from(texts: Vec<Text>) -> Self {
+        Self(
+            texts
+                .into_iter()
+                .map(|t| {
+                    let lang = if t.lang.is_empty() {
+                        DEFAULT_LANGUAGE.to_string()
+                    } else {
+                        t.lang
+                    };
+                    (lang, t.value)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl From<Translations> for Vec<Text> {
+    fn from(translations: Translations) -> Self {
+        translations
+            .0
+            .into_iter()
+            .map(|(lang, value)| Text { lang, value })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct History {
-    #[serde(rename = "Update")]
+    #[serde(rename = "Update", default)]
     pub updates: Vec<Update>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+/// Requirement a dependency places on the version of its target
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    Version(String),
+    VersionFrom(String),
+    VersionTo(String),
+    Release(u64),
+    ReleaseFrom(u64),
+    ReleaseTo(u64),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Dependency {
-    #[serde(rename = "$value")]
-    pub value: String,
+    #[serde(rename = "$text")]
+    pub value: PackageName,
+    #[serde(rename = "@version", skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(rename = "@versionFrom", skip_serializing_if = "Option::is_none")]
+    pub version_from: Option<String>,
+    #[serde(rename = "@versionTo", skip_serializing_if = "Option::is_none")]
+    pub version_to: Option<String>,
+    #[serde(rename = "@release", skip_serializing_if = "Option::is_none")]
+    pub release: Option<u64>,
+    #[serde(rename = "@releaseFrom", skip_serializing_if = "Option::is_none")]
+    pub release_from: Option<u64>,
+    #[serde(rename = "@releaseTo", skip_serializing_if = "Option::is_none")]
+    pub release_to: Option<u64>,
+}
+
+impl Dependency {
+    /// All constraints from the attributes, which must hold together
+    pub fn constraints(&self) -> Vec<Constraint> {
+        let versions = [
+            self.version.clone().map(Constraint::Version),
+            self.version_from.clone().map(Constraint::VersionFrom),
+            self.version_to.clone().map(Constraint::VersionTo),
+        ];
+        let releases = [
+            self.release.map(Constraint::Release),
+            self.release_from.map(Constraint::ReleaseFrom),
+            self.release_to.map(Constraint::ReleaseTo),
+        ];
+        versions.into_iter().chain(releases).flatten().collect()
+    }
 }
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct RuntimeDependencies {
     #[serde(rename = "Dependency")]
     pub deps: Vec<Dependency>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+/// Packages that cannot be installed alongside this one
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Conflicts {
+    #[serde(rename = "Package", default)]
+    pub packages: Vec<PackageName>,
+}
+
+/// Packages superseded by this one, usually after a rename
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Replaces {
+    #[serde(rename = "Package", default)]
+    pub packages: Vec<PackageName>,
+}
+
+/// Post-install script registered with COMAR
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Comar {
+    #[serde(rename = "@script")]
+    pub script: String,
+    /// Model the script implements, such as `System.Package`
+    #[serde(rename = "$text")]
+    pub model: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Provides {
+    #[serde(rename = "PkgConfig", default)]
+    pub pkgconfig: Vec<String>,
+    #[serde(rename = "PkgConfig32", default)]
+    pub pkgconfig32: Vec<String>,
+    #[serde(rename = "COMAR", default)]
+    pub comar: Vec<Comar>,
+}
+
+/// Delta from an older release to this package
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Delta {
+    #[serde(rename = "@releaseFrom")]
+    pub release_from: u64,
+    #[serde(rename = "PackageURI")]
+    pub package_uri: String,
+    pub package_size: u64,
+    /// sha1sum of the delta archive
+    pub package_hash: String,
+}
+
+/// Deltas published alongside the package, which are never fetched
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DeltaPackages {
+    #[serde(rename = "Delta", default)]
+    pub deltas: Vec<Delta>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Update {
+    #[serde(rename = "@release")]
     pub release: u64,
+    /// Set to `security` for security fixes
+    #[serde(rename = "@type", skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<String>,
+    /// As written in the index, see [`Update::date`]
     #[serde(rename = "Date")]
-    pub date: String,
+    pub raw_date: String,
     #[serde(rename = "Version")]
     pub version: String,
+    #[serde(rename = "Comment", default)]
+    pub comment: String,
+    #[serde(rename = "Name", default)]
+    pub name: String,
+    #[serde(rename = "Email", default)]
+    pub email: String,
+}
+
+/// Update dates are mostly ISO 8601, but some older entries use
+/// `MM-DD-YYYY` or `DD-MM-YYYY`
+///
+/// Formats are tried in order, so a date valid either way round, such as
+/// `03-04-2013`, is read month first. ISO dates with the day and month
+/// swapped can't be told apart from real ones, so they are not guessed at.
+mod date {
+    use chrono::NaiveDate;
+
+    const FORMATS: [&str; 3] = ["%Y-%m-%d", "%m-%d-%Y", "%d-%m-%Y"];
+
+    pub fn parse(date: &str) -> Option<NaiveDate> {
+        FORMATS
+            .iter()
+            .find_map(|f| NaiveDate::parse_from_str(date.trim(), f).ok())
+    }
+}
+
+impl Update {
+    /// Date of the update, unless the index records one that can't be read
+    pub fn date(&self) -> Option<NaiveDate> {
+        date::parse(&self.raw_date)
+    }
+
+    pub fn is_security(&self) -> bool {
+        self.r#type.as_deref() == Some("security")
+    }
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+/// Maintainer of the source package
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
-pub struct Source {
+pub struct Packager {
     pub name: String,
+    pub email: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Source {
+    pub name: SourceName,
     pub homepage: Option<String>,
+    pub packager: Option<Packager>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Package {
-    pub name: String,
-    pub summary: String,
-    pub description: String,
+    pub name: PackageName,
+    #[serde(default)]
+    pub summary: Translations,
+    #[serde(default)]
+    pub description: Translations,
     pub part_of: Option<String>,
     #[serde(rename = "PackageURI")]
     pub package_uri: String,
     #[serde(rename = "PackageSize")]
     pub package_size: u64,
+    /// Size of the extracted payload
+    #[serde(default)]
+    pub installed_size: u64,
     pub package_hash: String,
+    /// Missing from some third party indexes
+    #[serde(default)]
     pub history: History,
     pub source: Source,
-    #[serde(rename = "License")]
+    #[serde(rename = "License", default)]
     pub licenses: Vec<String>,
     #[serde(rename = "RuntimeDependencies")]
     pub run_deps: Option<RuntimeDependencies>,
+    pub conflicts: Option<Conflicts>,
+    pub replaces: Option<Replaces>,
+    pub provides: Option<Provides>,
+    pub delta_packages: Option<DeltaPackages>,
+    #[serde(default)]
+    pub build_host: String,
+    #[serde(default)]
+    pub distribution: String,
+    #[serde(default)]
+    pub distribution_release: String,
+    #[serde(default)]
+    pub architecture: String,
+    #[serde(default)]
+    pub package_format: String,
+    /// Repository serving the package when it is not on the mirrors
+    #[serde(skip)]
+    pub repository: Option<Url>,
+}
+
+impl Package {
+    /// Location of the archive, relative to `mirror` unless the package
+    /// came from another repository
+    pub fn uri(&self, mirror: &Url) -> Result<Url, url::ParseError> {
+        self.repository
+            .as_ref()
+            .unwrap_or(mirror)
+            .join(&self.package_uri)
+    }
+
+    /// Date of the most recent update
+    pub fn last_updated(&self) -> Option<NaiveDate> {
+        self.history.updates.iter().filter_map(Update::date).max()
+    }
+
+    /// COMAR scripts run by eopkg on install, which need manual porting
+    pub fn comar_scripts(&self) -> &[Comar] {
+        self.provides
+            .as_ref()
+            .map(|p| p.comar.as_slice())
+            .unwrap_or_default()
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Obsoletes {
-    #[serde(rename = "$value")]
-    pub packages: Vec<String>,
+    #[serde(rename = "Package", default)]
+    pub packages: Vec<PackageName>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Distro {
     pub source_name: String,
+    #[serde(default)]
+    pub description: Translations,
     pub version: String,
     pub r#type: String,
+    pub binary_name: Option<String>,
     pub obsoletes: Obsoletes,
 }
 
-#[derive(Debug, Deserialize)]
+/// A component packages declare themselves `PartOf`
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Component {
+    pub name: String,
+    #[serde(default)]
+    pub local_name: Translations,
+    #[serde(default)]
+    pub summary: Translations,
+    #[serde(default)]
+    pub description: Translations,
+    pub group: Option<String>,
+    pub maintainer: Option<Packager>,
+}
+
+impl Component {
+    /// Name of the enclosing component, `system` for `system.base`
+    pub fn parent(&self) -> Option<&str> {
+        component_parent(&self.name)
+    }
+}
+
+/// Name of the component enclosing `name` in the dotted hierarchy
+pub fn component_parent(name: &str) -> Option<&str> {
+    name.rsplit_once('.').map(|(parent, _)| parent)
+}
+
+/// Whether `component` is selected by `pattern`, which is either an exact
+/// name or a subtree such as `system.*`. A lone `*` selects everything.
+pub fn component_matches(pattern: &str, component: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some("") => true,
+        Some(prefix) => {
+            component.starts_with(prefix) || Some(component) == prefix.strip_suffix('.')
+        }
+        None => pattern == component,
+    }
+}
+
+/// Top level grouping of components, such as `desktop` or `multimedia`
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Group {
+    pub name: String,
+    #[serde(default)]
+    pub local_name: Translations,
+    pub icon: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename = "PISI", rename_all = "PascalCase")]
 pub struct Index {
     pub distribution: Distro,
-    #[serde(rename = "Package")]
+    #[serde(rename = "Package", default)]
     pub packages: Vec<Package>,
+    #[serde(rename = "Component", default)]
+    pub components: Vec<Component>,
+    /// Solus places these directly below the root rather than in `<Groups>`
+    #[serde(rename = "Group", default)]
+    pub groups: Vec<Group>,
+}
+
+/// Which package wins when several merged indexes provide the same name
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precedence {
+    /// The earliest index listing the package
+    First,
+    /// The latest index listing the package, as for overlays
+    Last,
+    /// The highest version and release, falling back to the earliest
+    Newest,
+}
+
+impl FromStr for Precedence {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Precedence::First),
+            "last" => Ok(Precedence::Last),
+            "newest" => Ok(Precedence::Newest),
+            _ => Err(Error::InvalidPrecedence(s.to_string())),
+        }
+    }
+}
+
+impl Index {
+    /// Merge several indexes into one, resolving packages of the same name
+    /// by `precedence`. The distribution header of the first index is kept.
+    pub fn merge(indexes: impl IntoIterator<Item = Index>, precedence: Precedence) -> Option<Self> {
+        let mut indexes = indexes.into_iter();
+        let mut merged = indexes.next()?;
+        let mut packages: BTreeMap<_, _> = merged
+            .packages
+            .drain(..)
+            .map(|p| (p.name.clone(), p))
+            .collect();
+
+        for index in indexes {
+            for package in index.packages {
+                match packages.entry(package.name.clone()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(package);
+                    }
+                    Entry::Occupied(mut entry) => {
+                        let release = |p: &Package| p.history.latest().map(|u| u.to_release());
+                        let replace = match precedence {
+                            Precedence::First => false,
+                            Precedence::Last => true,
+                            Precedence::Newest => release(&package) > release(entry.get()),
+                        };
+                        if replace {
+                            entry.insert(package);
+                        }
+                    }
+                }
+            }
+            for component in index.components {
+                if merged.component(&component.name).is_none() {
+                    merged.components.push(component);
+                }
+            }
+            for group in index.groups {
+                if merged.group(&group.name).is_none() {
+                    merged.groups.push(group);
+                }
+            }
+        }
+
+        merged.packages = packages.into_values().collect();
+        Some(merged)
+    }
+
+    /// Copy of the index keeping only the packages matching `keep`
+    pub fn filtered(&self, keep: impl Fn(&Package) -> bool) -> Self {
+        Self {
+            packages: self.packages.iter().filter(|p| keep(p)).cloned().collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Serialize the index back to XML
+    pub fn write(&self, mut writer: impl Write, compression: Compression) -> Result<(), Error> {
+        let xml = quick_xml::se::to_string(self)?;
+        match compression {
+            Compression::None => writer.write_all(xml.as_bytes())?,
+            Compression::Xz => writer.write_all(&lzma::compress(xml.as_bytes(), 6)?)?,
+            Compression::Zstd => writer.write_all(&zstd::encode_all(xml.as_bytes(), 0)?)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Look up the definition of a component by name
+    pub fn component(&self, name: &str) -> Option<&Component> {
+        self.components.iter().find(|c| c.name == name)
+    }
+
+    /// Look up the definition of a group by name
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|g| g.name == name)
+    }
+
+    /// Components selected by `pattern`, see [`component_matches`]
+    pub fn components_matching<'a>(
+        &'a self,
+        pattern: &'a str,
+    ) -> impl Iterator<Item = &'a Component> {
+        self.components
+            .iter()
+            .filter(move |c| component_matches(pattern, &c.name))
+    }
+
+    /// Packages whose component is selected by `pattern`
+    pub fn packages_in<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = &'a Package> {
+        self.packages.iter().filter(move |p| {
+            p.part_of
+                .as_deref()
+                .is_some_and(|c| component_matches(pattern, c))
+        })
+    }
+
+    /// Date of the most recent update of any package, which dates the
+    /// snapshot of the repository the index describes
+    pub fn last_updated(&self) -> Option<NaiveDate> {
+        self.packages.iter().filter_map(Package::last_updated).max()
+    }
+
+    /// Packages grouped by their component, skipping those without one
+    pub fn by_component(&self) -> BTreeMap<&str, Vec<&Package>> {
+        let mut grouped = BTreeMap::<_, Vec<_>>::new();
+        for p in &self.packages {
+            if let Some(component) = &p.part_of {
+                grouped.entry(component.as_str()).or_default().push(p);
+            }
+        }
+        grouped
+    }
+
+    /// Components belonging to `group`
+    pub fn components_in(&self, group: &str) -> impl Iterator<Item = &Component> {
+        let group = group.to_string();
+        self.components
+            .iter()
+            .filter(move |c| c.group.as_deref() == Some(group.as_str()))
+    }
+}
+
+/// Compression of an index file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Identify the compression from the leading bytes of the file
+    pub fn detect(header: &[u8]) -> Self {
+        if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Compression implied by the extension of `path`
+    pub fn from_extension(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("xz") => Compression::Xz,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Wrap `reader` in the decompressor matching its contents
+pub fn decompress<'a>(mut reader: impl BufRead + 'a) -> Result<Box<dyn BufRead + 'a>, Error> {
+    Ok(match Compression::detect(reader.fill_buf()?) {
+        Compression::None => Box::new(reader),
+        Compression::Xz => Box::new(BufReader::new(LzmaReader::new_decompressor(reader)?)),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+    })
+}
+
+/// Copy the events of the element opened by `start` into a standalone document
+fn capture<R: BufRead>(
+    reader: &mut quick_xml::Reader<R>,
+    buf: &mut Vec<u8>,
+    start: BytesStart<'static>,
+) -> Result<Vec<u8>, Error> {
+    let mut writer = quick_xml::Writer::new(vec![]);
+    writer.write_event(Event::Start(start))?;
+
+    let mut depth = 1;
+    while depth > 0 {
+        buf.clear();
+        let event = reader.read_event_into(buf)?;
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => return Err(Error::Truncated),
+            _ => {}
+        }
+        writer.write_event(event)?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Reader counting the lines of the underlying stream as it is consumed
+struct LineTracker<R> {
+    inner: R,
+    consumed: usize,
+    /// Newlines seen so far
+    lines: usize,
+    /// Offset just past the last newline
+    line_start: usize,
+}
+
+impl<R: BufRead> LineTracker<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            consumed: 0,
+            lines: 0,
+            line_start: 0,
+        }
+    }
+
+    /// Line and column, both starting at 1, of the byte at `offset`, which
+    /// must be on the line currently being consumed
+    fn locate(&self, offset: usize) -> Location {
+        Location {
+            offset,
+            line: self.lines + 1,
+            column: offset.saturating_sub(self.line_start) + 1,
+        }
+    }
+}
+
+impl<R: BufRead> Read for LineTracker<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for LineTracker<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buf) = self.inner.fill_buf() {
+            let consumed = &buf[..amt.min(buf.len())];
+            if let Some(last) = consumed.iter().rposition(|&b| b == b'\n') {
+                self.lines += consumed.iter().filter(|&&b| b == b'\n').count();
+                self.line_start = self.consumed + last + 1;
+            }
+        }
+        self.consumed += amt;
+        self.inner.consume(amt);
+    }
+}
+
+/// Position within the decompressed index
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    /// Byte offset from the start of the document
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// An element of the index that failed to deserialize
+#[derive(Debug)]
+pub struct Invalid {
+    /// Tag of the element, such as `Package` or `Component`
+    pub element: String,
+    /// Name of the package, if that much could be parsed
+    pub name: Option<PackageName>,
+    /// Path to the offending field within the element, such as
+    /// `History.Update[0].Date`
+    pub field: String,
+    /// Where the element starts
+    pub location: Location,
+    pub reason: quick_xml::DeError,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.element)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name}")?;
+        }
+        write!(
+            f,
+            " at {}, field {}: {}",
+            self.location, self.field, self.reason
+        )
+    }
+}
+
+/// Deserialize a captured element, tracking the path to any failing field
+fn deserialize<T: DeserializeOwned>(xml: &[u8]) -> Result<T, (String, quick_xml::DeError)> {
+    let mut de = quick_xml::de::Deserializer::from_reader(xml);
+    serde_path_to_error::deserialize(&mut de).map_err(|e| (e.path().to_string(), e.into_inner()))
+}
+
+/// Parse an index, reporting the location and field of the first
+/// malformed element
+pub fn parse(reader: impl BufRead) -> Result<Index, Error> {
+    parse_with(reader, |invalid| Err(Error::Invalid(Box::new(invalid))))
+}
+
+/// Parse an index, leaving out the packages that fail to deserialize
+/// instead of failing altogether
+pub fn parse_lenient(reader: impl BufRead) -> Result<(Index, Vec<Invalid>), Error> {
+    let mut skipped = vec![];
+    let index = parse_with(reader, |invalid| {
+        if invalid.element == "Package" {
+            skipped.push(invalid);
+            Ok(())
+        } else {
+            Err(Error::Invalid(Box::new(invalid)))
+        }
+    })?;
+    Ok((index, skipped))
+}
+
+/// Parse an index element by element, handing malformed ones to `invalid`
+fn parse_with(
+    reader: impl BufRead,
+    mut invalid: impl FnMut(Invalid) -> Result<(), Error>,
+) -> Result<Index, Error> {
+    #[derive(Deserialize)]
+    struct Named {
+        #[serde(rename = "Name")]
+        name: Option<PackageName>,
+    }
+
+    let mut reader = quick_xml::Reader::from_reader(LineTracker::new(reader));
+    let mut buf = vec![];
+    let mut depth: usize = 0;
+    let mut distribution = None;
+    let (mut packages, mut components, mut groups) = (vec![], vec![], vec![]);
+
+    loop {
+        buf.clear();
+        let position = reader.buffer_position();
+        let event = reader.read_event_into(&mut buf)?;
+        match event {
+            Event::Start(start) if depth == 1 => {
+                // Located before capturing moves the reader past the element
+                let location = reader.get_ref().locate(position);
+                let start = start.into_owned();
+                let element = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+                let xml = capture(&mut reader, &mut buf, start)?;
+                let xml = xml.as_slice();
+                let report = |(field, reason)| Invalid {
+                    name: quick_xml::de::from_reader::<_, Named>(xml)
+                        .ok()
+                        .and_then(|n| n.name),
+                    element: element.clone(),
+                    field,
+                    location,
+                    reason,
+                };
+                let result = match element.as_str() {
+                    "Package" => deserialize(xml).map(|p| packages.push(p)),
+                    "Distribution" => deserialize(xml).map(|d| distribution = Some(d)),
+                    "Component" => deserialize(xml).map(|c| components.push(c)),
+                    "Group" => deserialize(xml).map(|g| groups.push(g)),
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    invalid(report(e))?;
+                }
+            }
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth = depth.saturating_sub(1),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(Index {
+        distribution: distribution.ok_or(Error::MissingDistribution)?,
+        packages,
+        components,
+        groups,
+    })
+}
+
+/// Iterator parsing the packages of an index one at a time, ending after
+/// the first error since the reader can't resume from it
+pub struct Packages<R> {
+    reader: quick_xml::Reader<R>,
+    buf: Vec<u8>,
+    depth: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Packages<R> {
+    /// Iterate the packages of an uncompressed index
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: quick_xml::Reader::from_reader(reader),
+            buf: vec![],
+            depth: 0,
+            done: false,
+        }
+    }
+
+    fn parse(&mut self, start: BytesStart<'static>) -> Result<Package, Error> {
+        let xml = capture(&mut self.reader, &mut self.buf, start)?;
+        Ok(quick_xml::de::from_reader(xml.as_slice())?)
+    }
+}
+
+impl<R: BufRead> Iterator for Packages<R> {
+    type Item = Result<Package, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.buf.clear();
+            let event = match self.reader.read_event_into(&mut self.buf) {
+                Ok(event) => event,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            };
+            match event {
+                // Only direct children of the root, `<Package>` is reused for relations
+                Event::Start(start) if self.depth == 1 && start.name().as_ref() == b"Package" => {
+                    let start = start.into_owned();
+                    let package = self.parse(start);
+                    self.done = package.is_err();
+                    return Some(package);
+                }
+                Event::Start(_) => self.depth += 1,
+                Event::End(_) => self.depth = self.depth.saturating_sub(1),
+                Event::Eof => return None,
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+impl<R: BufRead> FusedIterator for Packages<R> {}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("lzma: {0}")]
+    Lzma(#[from] lzma::LzmaError),
+
+    #[error("xml: {0}")]
+    Xml(#[from] quick_xml::Error),
+
+    #[error("xml: {0}")]
+    De(#[from] quick_xml::DeError),
+
+    #[error("index ended inside an element")]
+    Truncated,
+
+    #[error("index has no distribution header")]
+    MissingDistribution,
+
+    #[error("invalid {0}")]
+    Invalid(Box<Invalid>),
+
+    #[error("invalid precedence: {0}, expected first, last or newest")]
+    InvalidPrecedence(String),
 }
 
 #[cfg(test)]
 mod test {
     use std::io::Cursor;
 
-    use lzma::LzmaReader;
-    use serde_xml_rs::from_reader;
+    use quick_xml::de::from_reader;
+
+    fn load() -> super::Index {
+        let reader =
+            super::decompress(Cursor::new(include_bytes!("../../test/eopkg-index.xml.xz")))
+                .unwrap();
+        from_reader(reader).unwrap()
+    }
 
     #[test]
     fn basic_index() {
-        let reader = LzmaReader::new_decompressor(Cursor::new(include_bytes!(
-            "../../test/eopkg-index.xml.xz"
-        )))
-        .unwrap();
-        let doc: super::Index = from_reader(reader).unwrap();
+        let doc = load();
 
         // Collect all *8* subpackages of zlib and itself
         let zlib = doc
@@ -108,8 +922,370 @@ mod test {
         let latest = &zlib[0].history.updates[0];
         assert_eq!(latest.version, "1.3");
         assert_eq!(latest.release, 26);
+        assert_eq!(latest.name, "Reilly Brogan");
+        assert!(latest.comment.starts_with("zlib: Don't build minizip"));
+        assert!(!latest.is_security());
+
+        let xerces = doc
+            .packages
+            .iter()
+            .find(|p| p.name == "xerces-c-devel")
+            .unwrap();
+        assert!(xerces.history.updates[0].is_security());
 
         let dep = zlib[0].run_deps.clone().expect("No dependencies");
         assert_eq!(dep.deps[0].value, "glibc");
+        assert_eq!(
+            dep.deps[0].constraints(),
+            [super::Constraint::ReleaseFrom(90)]
+        );
+
+        assert_eq!(zlib[0].architecture, "x86_64");
+        assert_eq!(zlib[0].distribution, "Solus");
+        assert_eq!(zlib[0].package_format, "1.2");
+        assert_eq!(zlib[0].summary.get("de"), "zlib (Compression library)");
+
+        let packager = zlib[0].source.packager.as_ref().unwrap();
+        assert_eq!(packager.name, "Reilly Brogan");
+        assert_eq!(packager.email, "reilly@reillybrogan.com");
+    }
+
+    #[test]
+    fn relations() {
+        let doc = load();
+        let find = |name: &str| doc.packages.iter().find(|p| p.name == name).unwrap();
+
+        let displaycal = find("displaycal").conflicts.as_ref().unwrap();
+        assert_eq!(displaycal.packages, ["python-pyglet"]);
+        assert!(find("zlib").conflicts.is_none());
+
+        let apostrophe = find("apostrophe").replaces.as_ref().unwrap();
+        assert_eq!(apostrophe.packages, ["uberwriter"]);
+
+        let pisi = find("pisi").comar_scripts();
+        assert_eq!(pisi[0].script, "package.py");
+        assert_eq!(pisi[0].model, "System.Package");
+        assert!(find("zlib").comar_scripts().is_empty());
+    }
+
+    #[test]
+    fn deltas() {
+        let package: super::Package = quick_xml::de::from_str(
+            r#"<Package>
+                <Name>zlib</Name>
+                <Summary xml:lang="en">Compression library</Summary>
+                <Description xml:lang="en">Compression library</Description>
+                <PackageURI>z/zlib/zlib-1.3-26-1-x86_64.eopkg</PackageURI>
+                <PackageSize>63040</PackageSize>
+                <InstalledSize>110362</InstalledSize>
+                <PackageHash>9f1133ca8914518dcbd4ecf77910129c3df0ec08</PackageHash>
+                <History>
+                    <Update release="26"><Date>2023-08-20</Date><Version>1.3</Version></Update>
+                </History>
+                <Source><Name>zlib</Name></Source>
+                <License>ZLIB</License>
+                <DeltaPackages>
+                    <Delta releaseFrom="25">
+                        <PackageURI>z/zlib/zlib-25-26-1-x86_64.delta.eopkg</PackageURI>
+                        <PackageSize>4096</PackageSize>
+                        <PackageHash>0e5751c026e543b2e8ab2eb06099daa1d1e5df47</PackageHash>
+                    </Delta>
+                    <Delta releaseFrom="24">
+                        <PackageURI>z/zlib/zlib-24-26-1-x86_64.delta.eopkg</PackageURI>
+                        <PackageSize>8192</PackageSize>
+                        <PackageHash>5c10b5b2cd673a0616d529aa5234b12ee7153808</PackageHash>
+                    </Delta>
+                </DeltaPackages>
+                <BuildHost>solus</BuildHost>
+                <Distribution>Solus</Distribution>
+                <DistributionRelease>1</DistributionRelease>
+                <Architecture>x86_64</Architecture>
+                <PackageFormat>1.2</PackageFormat>
+            </Package>"#,
+        )
+        .unwrap();
+
+        let deltas = package.delta_packages.unwrap().deltas;
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[1].release_from, 24);
+        assert_eq!(deltas[1].package_size, 8192);
+        assert_eq!(
+            deltas[1].package_hash,
+            "5c10b5b2cd673a0616d529aa5234b12ee7153808"
+        );
+    }
+
+    #[test]
+    fn distribution() {
+        let doc = load();
+        let distro = &doc.distribution;
+        assert_eq!(distro.source_name, "Solus");
+        assert_eq!(distro.binary_name.as_deref(), Some("Solus"));
+        assert_eq!(distro.description.get("en"), "Solus Repository");
+        assert_eq!(distro.description.get("de"), "Solus-Repository");
+        assert_eq!(distro.version, "1");
+        assert_eq!(distro.r#type, "main");
+        assert!(distro
+            .obsoletes
+            .packages
+            .iter()
+            .any(|p| p == "chrome-gnome-shell"));
+    }
+
+    #[test]
+    fn components() {
+        let doc = load();
+        assert_eq!(doc.components.len(), 82);
+
+        let budgie = doc.component("desktop.budgie").unwrap();
+        assert_eq!(budgie.local_name.get("en"), "Budgie Desktop");
+        assert_eq!(budgie.local_name.get("de"), "Budgie-Desktop");
+        assert_eq!(budgie.group.as_deref(), Some("desktop"));
+        assert_eq!(budgie.maintainer.as_ref().unwrap().name, "Solus Team");
+
+        assert_eq!(doc.groups.len(), 8);
+        let desktop = doc.group("desktop").unwrap();
+        assert_eq!(desktop.local_name.get("en"), "Desktop Software & Theming");
+        assert_eq!(desktop.icon.as_deref(), Some("user-desktop"));
+        assert!(doc
+            .components_in("desktop")
+            .any(|c| c.name == "desktop.budgie"));
+    }
+
+    #[test]
+    fn component_hierarchy() {
+        use super::component_matches;
+
+        assert!(component_matches("system.*", "system.base"));
+        assert!(component_matches("system.*", "system"));
+        assert!(!component_matches("system.*", "desktop.budgie"));
+        assert!(!component_matches("system", "system.base"));
+        assert!(component_matches("*", "desktop.budgie"));
+
+        let doc = load();
+        let budgie = doc.component("desktop.budgie").unwrap();
+        assert_eq!(budgie.parent(), Some("desktop"));
+        assert_eq!(doc.components_matching("programming.*").count(), 10);
+        assert_eq!(doc.packages_in("system.*").count(), 517);
+        assert_eq!(doc.packages_in("system.base").count(), 121);
+        assert_eq!(doc.by_component()["system.base"].len(), 121);
+    }
+
+    #[test]
+    fn compression() {
+        use super::{decompress, Compression};
+
+        let xz = include_bytes!("../../test/eopkg-index.xml.xz");
+        assert_eq!(Compression::detect(xz), Compression::Xz);
+        assert_eq!(Compression::detect(b"<PISI>"), Compression::None);
+
+        let plain = b"<PISI><Distribution><SourceName>Solus</SourceName>\
+            <Version>1</Version><Type>main</Type><Obsoletes><Package>zlib-minizip</Package></Obsoletes>\
+            </Distribution></PISI>";
+        let doc: super::Index = from_reader(decompress(&plain[..]).unwrap()).unwrap();
+        assert_eq!(doc.distribution.source_name, "Solus");
+        assert!(doc.packages.is_empty());
+    }
+
+    #[test]
+    fn streaming() {
+        let reader =
+            super::decompress(Cursor::new(include_bytes!("../../test/eopkg-index.xml.xz")))
+                .unwrap();
+        let packages = super::Packages::new(reader)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(packages.len(), 10642);
+
+        let displaycal = packages.iter().find(|p| p.name == "displaycal").unwrap();
+        assert_eq!(
+            displaycal.conflicts.as_ref().unwrap().packages,
+            ["python-pyglet"]
+        );
+        assert!(packages
+            .iter()
+            .any(|p| p.name == "zlib" && p.part_of.as_deref() == Some("system.base")));
+    }
+
+    #[test]
+    fn truncated_stream() {
+        // The error ends the iteration rather than repeating
+        let truncated = b"<PISI><Package><Name>zlib</Name><Source><Name>zlib";
+        let mut packages = super::Packages::new(&truncated[..]);
+        assert!(matches!(
+            packages.next(),
+            Some(Err(super::Error::Truncated))
+        ));
+        assert!(packages.next().is_none());
+
+        // Nor does it resume after a malformed package
+        let malformed = b"<PISI><Package><Name>zlib</Name><PackageSize>big</PackageSize></Package>\
+            <Package><Name>zstd</Name></Package></PISI>";
+        let mut packages = super::Packages::new(&malformed[..]);
+        assert!(matches!(packages.next(), Some(Err(_))));
+        assert!(packages.next().is_none());
+    }
+
+    #[test]
+    fn write_back() {
+        use super::{decompress, Compression, Index};
+
+        let trimmed = load().filtered(|p| p.source.name == "zlib");
+        let mut xml = vec![];
+        trimmed.write(&mut xml, Compression::Xz).unwrap();
+
+        let doc: Index = from_reader(decompress(xml.as_slice()).unwrap()).unwrap();
+        assert_eq!(doc.packages.len(), 6);
+        assert_eq!(doc.distribution.source_name, "Solus");
+        assert_eq!(doc.components.len(), 82);
+
+        let (ours, theirs) = (&trimmed.packages[0], &doc.packages[0]);
+        assert_eq!(ours.name, theirs.name);
+        assert_eq!(ours.summary, theirs.summary);
+        assert_eq!(ours.package_hash, theirs.package_hash);
+        assert_eq!(ours.history.updates.len(), theirs.history.updates.len());
+        assert_eq!(
+            ours.run_deps.as_ref().unwrap().deps[0].constraints(),
+            theirs.run_deps.as_ref().unwrap().deps[0].constraints()
+        );
+    }
+
+    #[test]
+    fn optional_fields() {
+        let package: super::Package = quick_xml::de::from_str(
+            r#"<Package>
+                <Name>overlay</Name>
+                <PackageURI>o/overlay/overlay-1-1-1-x86_64.eopkg</PackageURI>
+                <PackageSize>1024</PackageSize>
+                <PackageHash>da39a3ee5e6b4b0d3255bfef95601890afd80709</PackageHash>
+                <Source><Name>overlay</Name></Source>
+            </Package>"#,
+        )
+        .unwrap();
+        assert!(package.history.updates.is_empty());
+        assert!(package.licenses.is_empty());
+        assert_eq!(package.summary.get("en"), "");
+    }
+
+    #[test]
+    fn dates() {
+        use chrono::NaiveDate;
+
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d);
+        assert_eq!(super::date::parse("2023-10-14"), date(2023, 10, 14));
+        assert_eq!(super::date::parse("10-27-2023"), date(2023, 10, 27));
+        assert_eq!(super::date::parse("27-10-2023"), date(2023, 10, 27));
+        assert_eq!(super::date::parse("2013-14-03"), None);
+        assert_eq!(super::date::parse("2023-13-14"), None);
+
+        // Ambiguous dates are read month first
+        assert_eq!(super::date::parse("2013-03-04"), date(2013, 3, 4));
+        assert_eq!(super::date::parse("03-04-2013"), date(2013, 3, 4));
+
+        let doc = load();
+        let zlib = doc.packages.iter().find(|p| p.name == "zlib").unwrap();
+        assert_eq!(zlib.last_updated(), date(2023, 10, 14));
+    }
+
+    #[test]
+    fn unreadable_dates() {
+        use chrono::NaiveDate;
+
+        let xml = br#"<PISI>
+            <Distribution>
+                <SourceName>Solus</SourceName>
+                <Version>1</Version>
+                <Type>main</Type>
+                <Obsoletes><Package>nano-tiny</Package></Obsoletes>
+            </Distribution>
+            <Package>
+                <Name>nano</Name>
+                <PackageURI>n/nano/nano-2.3-2-1-x86_64.eopkg</PackageURI>
+                <PackageSize>1024</PackageSize>
+                <PackageHash>da39a3ee5e6b4b0d3255bfef95601890afd80709</PackageHash>
+                <Source><Name>nano</Name></Source>
+                <History>
+                    <Update release="2"><Date>2013-14-03</Date><Version>2.3</Version></Update>
+                    <Update release="1"><Date>2013-02-01</Date><Version>2.2</Version></Update>
+                </History>
+            </Package>
+        </PISI>"#;
+
+        // The package still loads, only the date is left out
+        let index = super::parse(&xml[..]).unwrap();
+        let nano = &index.packages[0];
+        assert_eq!(nano.history.updates[0].raw_date, "2013-14-03");
+        assert_eq!(nano.history.updates[0].date(), None);
+        assert_eq!(nano.last_updated(), NaiveDate::from_ymd_opt(2013, 2, 1));
+    }
+
+    #[test]
+    fn lenient() {
+        let xml = br#"<PISI>
+            <Distribution>
+                <SourceName>Solus</SourceName>
+                <Version>1</Version>
+                <Type>main</Type>
+                <Obsoletes><Package>zlib-minizip</Package></Obsoletes>
+            </Distribution>
+            <Package>
+                <Name>broken</Name>
+                <PackageSize>many</PackageSize>
+            </Package>
+            <Package>
+                <Name>overlay</Name>
+                <PackageURI>o/overlay/overlay-1-1-1-x86_64.eopkg</PackageURI>
+                <PackageSize>1024</PackageSize>
+                <PackageHash>da39a3ee5e6b4b0d3255bfef95601890afd80709</PackageHash>
+                <Source><Name>overlay</Name></Source>
+                <Replaces><Package>underlay</Package></Replaces>
+            </Package>
+        </PISI>"#;
+
+        assert!(from_reader::<_, super::Index>(&xml[..]).is_err());
+        let (index, skipped) = super::parse_lenient(&xml[..]).unwrap();
+        assert_eq!(index.packages.len(), 1);
+        assert_eq!(index.packages[0].name, "overlay");
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].name.as_deref(), Some("broken"));
+        assert_eq!(skipped[0].field, "PackageSize");
+        assert_eq!(skipped[0].location.line, 8);
+        assert_eq!(skipped[0].location.column, 13);
+
+        let Err(super::Error::Invalid(invalid)) = super::parse(&xml[..]) else {
+            panic!("expected the broken package to be reported");
+        };
+        assert_eq!(invalid.name.as_deref(), Some("broken"));
+    }
+
+    #[test]
+    fn merge() {
+        use super::{Index, Precedence};
+
+        let doc = load();
+        let zlib = |index: &Index| {
+            let p = index.packages.iter().find(|p| p.name == "zlib").unwrap();
+            p.history.updates[0].release
+        };
+        let mut overlay = doc.filtered(|p| p.name == "zlib" || p.name == "glibc");
+        overlay.packages[1].history.updates[0].release = 1;
+        overlay.packages[0].history.updates[0].release += 1;
+        assert_eq!(overlay.packages[1].name, "zlib");
+
+        let merged = Index::merge([doc.clone(), overlay.clone()], Precedence::First).unwrap();
+        assert_eq!(merged.packages.len(), doc.packages.len());
+        assert_eq!(zlib(&merged), 26);
+
+        let merged = Index::merge([doc.clone(), overlay.clone()], Precedence::Last).unwrap();
+        assert_eq!(zlib(&merged), 1);
+
+        let merged = Index::merge([doc.clone(), overlay], Precedence::Newest).unwrap();
+        assert_eq!(zlib(&merged), 26);
+        let glibc = merged.packages.iter().find(|p| p.name == "glibc").unwrap();
+        let original = doc.packages.iter().find(|p| p.name == "glibc").unwrap();
+        assert_eq!(
+            glibc.history.updates[0].release,
+            original.history.updates[0].release + 1
+        );
     }
 }
@@ -0,0 +1,155 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! metadata.xml parsing
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    index::{self, History, RuntimeDependencies, Source, Translations},
+    name::PackageName,
+};
+
+/// Package entry of metadata.xml, which unlike the index is never truncated
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Package {
+    pub name: PackageName,
+    pub summary: Translations,
+    pub description: Translations,
+    pub part_of: Option<String>,
+    #[serde(rename = "License", default)]
+    pub licenses: Vec<String>,
+    #[serde(rename = "RuntimeDependencies")]
+    pub run_deps: Option<RuntimeDependencies>,
+    #[serde(default)]
+    pub history: History,
+    pub installed_size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename = "PISI", rename_all = "PascalCase")]
+pub struct Metadata {
+    pub source: Source,
+    pub package: Package,
+}
+
+impl index::Package {
+    /// Replace the index fields with those from the archive metadata
+    pub fn apply_metadata(&mut self, metadata: Metadata) {
+        let Metadata { source, package } = metadata;
+        self.summary = package.summary;
+        self.description = package.description;
+        self.part_of = package.part_of.or(self.part_of.take());
+        if !package.licenses.is_empty() {
+            self.licenses = package.licenses;
+        }
+        self.run_deps = package.run_deps;
+        if !package.history.updates.is_empty() {
+            self.history = package.history;
+        }
+        self.source.homepage = source.homepage.or(self.source.homepage.take());
+        self.source.packager = source.packager.or(self.source.packager.take());
+    }
+}
+
+impl From<Metadata> for index::Package {
+    /// Index entry of an archive that is not listed in any index. The
+    /// location, size and hash of the archive are left for the caller.
+    fn from(metadata: Metadata) -> Self {
+        let mut package = Self {
+            name: metadata.package.name.clone(),
+            installed_size: metadata.package.installed_size.unwrap_or_default(),
+            source: Source {
+                name: metadata.source.name.clone(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        package.apply_metadata(metadata);
+        package
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use quick_xml::de::from_str;
+
+    use super::Metadata;
+    use crate::eopkg::index::{self, Translations};
+
+    #[test]
+    fn prefer_metadata() {
+        let metadata: Metadata = from_str(
+            r#"<PISI>
+                <Source>
+                    <Name>zlib</Name>
+                    <Homepage>https://zlib.net/</Homepage>
+                    <Packager><Name>Solus</Name><Email>root@getsol.us</Email></Packager>
+                </Source>
+                <Package>
+                    <Name>zlib</Name>
+                    <Summary xml:lang="en">Compression library</Summary>
+                    <Description xml:lang="en">A massively spiffy yet delicately unobtrusive compression library.</Description>
+                    <PartOf>system.base</PartOf>
+                    <License>ZLIB</License>
+                    <RuntimeDependencies>
+                        <Dependency releaseFrom="1">glibc</Dependency>
+                    </RuntimeDependencies>
+                    <History>
+                        <Update release="26">
+                            <Date>2023-08-20</Date>
+                            <Version>1.3</Version>
+                        </Update>
+                    </History>
+                    <InstalledSize>110362</InstalledSize>
+                </Package>
+            </PISI>"#,
+        )
+        .unwrap();
+
+        let mut package = index::Package {
+            name: "zlib".into(),
+            description: Translations([("en".into(), "A massively spiffy".into())].into()),
+            ..Default::default()
+        };
+        package.apply_metadata(metadata);
+
+        assert!(package
+            .description
+            .get("en")
+            .ends_with("compression library."));
+        assert_eq!(
+            package.source.homepage.as_deref(),
+            Some("https://zlib.net/")
+        );
+        assert_eq!(package.licenses, ["ZLIB"]);
+        assert_eq!(package.history.updates[0].release, 26);
+        assert_eq!(package.run_deps.unwrap().deps[0].value, "glibc");
+    }
+
+    #[test]
+    fn unindexed() {
+        let metadata: Metadata = from_str(
+            r#"<PISI>
+                <Source><Name>zlib</Name></Source>
+                <Package>
+                    <Name>zlib-devel</Name>
+                    <Summary xml:lang="en">Development files for zlib</Summary>
+                    <Description xml:lang="en">Development files for zlib</Description>
+                    <PartOf>system.devel</PartOf>
+                    <InstalledSize>4096</InstalledSize>
+                </Package>
+            </PISI>"#,
+        )
+        .unwrap();
+
+        let package = index::Package::from(metadata);
+        assert_eq!(package.name, "zlib-devel");
+        assert_eq!(package.source.name, "zlib");
+        assert_eq!(package.part_of.as_deref(), Some("system.devel"));
+        assert_eq!(package.installed_size, 4096);
+        assert_eq!(package.summary.get("en"), "Development files for zlib");
+    }
+}
@@ -3,4 +3,11 @@
 // SPDX-License-Identifier: MPL-2.0
 
 //! eopkg helpers
+pub mod changelog;
+pub mod files;
 pub mod index;
+pub mod metadata;
+pub mod name;
+pub mod package;
+pub mod pspec;
+pub mod version;
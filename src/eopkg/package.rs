@@ -0,0 +1,217 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! eopkg archive access
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use lzma::LzmaReader;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use zip::{read::ZipFile, ZipArchive};
+
+use super::{files::Files, metadata::Metadata};
+
+/// Well known members of an `.eopkg` archive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Member {
+    /// Package metadata, a superset of the index entry
+    Metadata,
+
+    /// Listing of every installed file
+    Files,
+
+    /// The xz compressed payload
+    Install,
+}
+
+impl Member {
+    /// Name of the member within the archive
+    pub fn name(&self) -> &'static str {
+        match self {
+            Member::Metadata => "metadata.xml",
+            Member::Files => "files.xml",
+            Member::Install => "install.tar.xz",
+        }
+    }
+}
+
+/// An opened `.eopkg` archive
+pub struct Archive<R> {
+    zip: ZipArchive<R>,
+}
+
+impl Archive<BufReader<File>> {
+    /// Open the archive at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Read an archive from any seekable source
+    pub fn new(reader: R) -> Result<Self, Error> {
+        let zip = ZipArchive::new(reader)?;
+        Ok(Self { zip })
+    }
+
+    /// Raw reader over a member of the archive
+    pub fn member(&mut self, member: Member) -> Result<ZipFile<'_>, Error> {
+        self.zip.by_name(member.name()).map_err(|e| match e {
+            zip::result::ZipError::FileNotFound => Error::MissingMember(member.name()),
+            e => Error::Zip(e),
+        })
+    }
+
+    /// Read a member fully into memory
+    pub fn read_member(&mut self, member: Member) -> Result<Vec<u8>, Error> {
+        let mut file = self.member(member)?;
+        let mut buf = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Parsed package metadata
+    pub fn metadata(&mut self) -> Result<Metadata, Error> {
+        Ok(quick_xml::de::from_reader(BufReader::new(
+            self.member(Member::Metadata)?,
+        ))?)
+    }
+
+    /// Parsed listing of the installed files
+    pub fn files(&mut self) -> Result<Files, Error> {
+        Ok(quick_xml::de::from_reader(BufReader::new(
+            self.member(Member::Files)?,
+        ))?)
+    }
+
+    /// Decompressed tar stream of the payload
+    pub fn install(&mut self) -> Result<LzmaReader<ZipFile<'_>>, Error> {
+        let file = self.member(Member::Install)?;
+        Ok(LzmaReader::new_decompressor(file)?)
+    }
+
+    /// Copy the compressed payload to `target` as a standalone tarball,
+    /// returning its SHA-256 digest
+    pub fn repack(&mut self, target: impl AsRef<Path>) -> Result<[u8; 32], Error> {
+        let mut member = self.member(Member::Install)?;
+        let mut output = BufWriter::new(File::create(target)?);
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let len = member.read(&mut buffer)?;
+            if len == 0 {
+                break;
+            }
+            hasher.update(&buffer[..len]);
+            output.write_all(&buffer[..len])?;
+        }
+        output.flush()?;
+        Ok(hasher.finalize().into())
+    }
+
+    /// Unpack the payload into `target`, keeping the recorded permissions
+    pub fn extract(&mut self, target: impl AsRef<Path>) -> Result<(), Error> {
+        let mut payload = tar::Archive::new(self.install()?);
+        payload.set_preserve_permissions(true);
+        payload.set_overwrite(true);
+        payload.unpack(target)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("zip: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
+    #[error("xml: {0}")]
+    Xml(#[from] quick_xml::DeError),
+
+    #[error("lzma: {0}")]
+    Lzma(#[from] lzma::LzmaError),
+
+    #[error("missing archive member: {0}")]
+    MissingMember(&'static str),
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::{Cursor, Write};
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::{Archive, Error, Member};
+
+    /// Build an in-memory archive from `(name, contents)` pairs
+    pub(crate) fn archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        for (name, contents) in members {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn members() {
+        let data = archive(&[("metadata.xml", b"<PISI/>"), ("files.xml", b"<Files/>")]);
+        let mut archive = Archive::new(Cursor::new(data)).unwrap();
+
+        assert_eq!(archive.read_member(Member::Metadata).unwrap(), b"<PISI/>");
+        assert_eq!(archive.read_member(Member::Files).unwrap(), b"<Files/>");
+        assert!(matches!(
+            archive.member(Member::Install),
+            Err(Error::MissingMember("install.tar.xz"))
+        ));
+    }
+
+    #[test]
+    fn extract() {
+        let mut payload = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        header.set_mode(0o755);
+        header.set_cksum();
+        payload
+            .append_data(&mut header, "usr/bin/hello", &b"#!/bin"[..])
+            .unwrap();
+        let payload = lzma::compress(&payload.into_inner().unwrap(), 6).unwrap();
+
+        let data = archive(&[("install.tar.xz", &payload)]);
+        let target = std::env::temp_dir().join(format!("pisi-extract-{}", std::process::id()));
+        Archive::new(Cursor::new(data))
+            .unwrap()
+            .extract(&target)
+            .unwrap();
+
+        let extracted = target.join("usr/bin/hello");
+        assert_eq!(std::fs::read(&extracted).unwrap(), b"#!/bin");
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn repack() {
+        use sha2::{Digest, Sha256};
+
+        let data = archive(&[("install.tar.xz", b"payload")]);
+        let target =
+            std::env::temp_dir().join(format!("pisi-repack-{}.tar.xz", std::process::id()));
+        let hash = Archive::new(Cursor::new(data))
+            .unwrap()
+            .repack(&target)
+            .unwrap();
+
+        assert_eq!(std::fs::read(&target).unwrap(), b"payload");
+        assert_eq!(hash, <[u8; 32]>::from(Sha256::digest(b"payload")));
+        std::fs::remove_file(&target).unwrap();
+    }
+}
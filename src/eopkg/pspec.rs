@@ -0,0 +1,234 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! pspec.xml source specification parsing
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    index::{Dependency, History, Packager, RuntimeDependencies, Translations},
+    name::{PackageName, SourceName},
+};
+
+/// Names a source directory may use for its specification
+const PSPEC_NAMES: [&str; 2] = ["pspec.xml", "pspec_x86_64.xml"];
+
+/// Upstream archive of the source
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Archive {
+    #[serde(rename = "@type")]
+    pub r#type: Option<String>,
+    #[serde(rename = "@sha1sum")]
+    pub sha1sum: Option<String>,
+    #[serde(rename = "$text")]
+    pub uri: String,
+    /// Digest of the archive once fetched, as boulder verifies upstreams
+    /// by sha256 rather than sha1
+    #[serde(skip)]
+    pub sha256: Option<[u8; 32]>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Patch {
+    /// Strip level passed to `patch -p`
+    #[serde(rename = "@level")]
+    pub level: Option<u32>,
+    #[serde(rename = "$text")]
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Patches {
+    #[serde(rename = "Patch", default)]
+    pub patches: Vec<Patch>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BuildDependencies {
+    #[serde(rename = "Dependency", default)]
+    pub deps: Vec<Dependency>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Source {
+    pub name: SourceName,
+    pub homepage: Option<String>,
+    pub packager: Option<Packager>,
+    #[serde(rename = "License", default)]
+    pub licenses: Vec<String>,
+    pub part_of: Option<String>,
+    #[serde(default)]
+    pub summary: Translations,
+    #[serde(default)]
+    pub description: Translations,
+    #[serde(rename = "Archive", default)]
+    pub archives: Vec<Archive>,
+    #[serde(rename = "BuildDependencies")]
+    pub build_deps: Option<BuildDependencies>,
+    pub patches: Option<Patches>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Package {
+    pub name: PackageName,
+    #[serde(rename = "RuntimeDependencies")]
+    pub run_deps: Option<RuntimeDependencies>,
+}
+
+/// A parsed pspec.xml
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename = "PISI", rename_all = "PascalCase")]
+pub struct Pspec {
+    pub source: Source,
+    #[serde(rename = "Package", default)]
+    pub packages: Vec<Package>,
+    pub history: Option<History>,
+}
+
+impl Pspec {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        Ok(quick_xml::de::from_reader(BufReader::new(file))?)
+    }
+
+    /// Load the specification of `source` from a checkout of the source
+    /// repository laid out as `<dir>/<source>/pspec.xml`, or as
+    /// `<dir>/packages/<letter>/<source>/pspec.xml` like the Solus monorepo
+    pub fn find(dir: &Path, source: &str) -> Result<Option<Self>, Error> {
+        let mut source_dirs = vec![dir.join(source)];
+        if let Some(letter) = source.chars().next() {
+            source_dirs.push(dir.join("packages").join(letter.to_string()).join(source));
+        }
+        let path = source_dirs
+            .iter()
+            .flat_map(|source_dir| PSPEC_NAMES.iter().map(|name| source_dir.join(name)))
+            .find(|p| p.is_file());
+        path.map(Self::load).transpose()
+    }
+
+    /// Names of the packages needed to build the source
+    pub fn build_deps(&self) -> impl Iterator<Item = &str> {
+        self.source
+            .build_deps
+            .iter()
+            .flat_map(|b| b.deps.iter())
+            .map(|d| d.value.as_str())
+    }
+
+    /// Paths of the patches applied to the source
+    pub fn patches(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.source
+            .patches
+            .iter()
+            .flat_map(|p| p.patches.iter())
+            .map(|p| PathBuf::from(&p.path))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("xml: {0}")]
+    Xml(#[from] quick_xml::DeError),
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use quick_xml::de::from_str;
+
+    use super::Pspec;
+
+    #[test]
+    fn parse_pspec() {
+        let pspec: Pspec = from_str(
+            r#"<PISI>
+                <Source>
+                    <Name>nano</Name>
+                    <Homepage>https://www.nano-editor.org/</Homepage>
+                    <Packager>
+                        <Name>Solus Team</Name>
+                        <Email>root@getsol.us</Email>
+                    </Packager>
+                    <License>GPL-3.0-or-later</License>
+                    <PartOf>system.utils</PartOf>
+                    <Summary xml:lang="en">Small, friendly text editor</Summary>
+                    <Description xml:lang="en">GNU nano is a small and friendly text editor.</Description>
+                    <Archive type="tarxz" sha1sum="7a6b8ebad0a3c3b2b4a3ba8ca9d4c341df9d3d8f">https://www.nano-editor.org/dist/v7/nano-7.2.tar.xz</Archive>
+                    <BuildDependencies>
+                        <Dependency>ncurses-devel</Dependency>
+                        <Dependency versionFrom="2.0">file-devel</Dependency>
+                    </BuildDependencies>
+                    <Patches>
+                        <Patch level="1">stateless.patch</Patch>
+                    </Patches>
+                </Source>
+                <Package>
+                    <Name>nano</Name>
+                    <RuntimeDependencies>
+                        <Dependency>ncurses</Dependency>
+                    </RuntimeDependencies>
+                </Package>
+                <History>
+                    <Update release="163">
+                        <Date>2023-01-18</Date>
+                        <Version>7.2</Version>
+                    </Update>
+                </History>
+            </PISI>"#,
+        )
+        .unwrap();
+
+        assert_eq!(pspec.source.name, "nano");
+        assert_eq!(
+            pspec.build_deps().collect::<Vec<_>>(),
+            ["ncurses-devel", "file-devel"]
+        );
+        let patches = pspec.patches().collect::<Vec<_>>();
+        assert_eq!(patches[0].to_str(), Some("stateless.patch"));
+        assert_eq!(
+            pspec.source.patches.as_ref().unwrap().patches[0].level,
+            Some(1)
+        );
+        assert_eq!(
+            pspec.source.archives[0].sha1sum.as_deref(),
+            Some("7a6b8ebad0a3c3b2b4a3ba8ca9d4c341df9d3d8f")
+        );
+        assert_eq!(pspec.history.unwrap().updates[0].release, 163);
+    }
+
+    #[test]
+    fn find_pspec() {
+        let dir = std::env::temp_dir().join(format!("pisi-pspec-{}", std::process::id()));
+        let pspec = "<PISI><Source><Name>nano</Name></Source></PISI>";
+        create_dir_all(dir.join("packages/n/nano")).unwrap();
+        write(dir.join("packages/n/nano/pspec.xml"), pspec).unwrap();
+        create_dir_all(dir.join("zlib")).unwrap();
+        write(
+            dir.join("zlib/pspec_x86_64.xml"),
+            pspec.replace("nano", "zlib"),
+        )
+        .unwrap();
+
+        let nano = Pspec::find(&dir, "nano").unwrap().expect("monorepo layout");
+        assert_eq!(nano.source.name, "nano");
+        let zlib = Pspec::find(&dir, "zlib").unwrap().expect("flat layout");
+        assert_eq!(zlib.source.name, "zlib");
+        assert!(Pspec::find(&dir, "vim").unwrap().is_none());
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
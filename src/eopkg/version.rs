@@ -0,0 +1,203 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! pisi version ordering
+
+use std::{cmp::Ordering, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use super::index::{Constraint, History, Update};
+
+/// Suffixes pisi orders relative to a plain release, e.g. `1.0rc1 < 1.0 < 1.0p1`
+const KEYWORDS: [(&str, i8); 6] = [
+    ("alpha", -5),
+    ("beta", -4),
+    ("pre", -3),
+    ("rc", -2),
+    ("m", -1),
+    ("p", 1),
+];
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Token<'a> {
+    Keyword(i8),
+    Alpha(&'a str),
+    Number(u64),
+}
+
+impl Token<'_> {
+    /// Whether the token marks a version before the plain release
+    fn is_prerelease(&self) -> bool {
+        matches!(self, Token::Keyword(rank) if *rank < 0)
+    }
+}
+
+/// Split a version into runs of digits and letters, ignoring separators
+fn tokens(version: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut rest = version;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric()) {
+        rest = &rest[start..];
+        let numeric = rest.starts_with(|c: char| c.is_ascii_digit());
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() || c.is_ascii_digit() != numeric)
+            .unwrap_or(rest.len());
+        let (run, tail) = rest.split_at(end);
+        tokens.push(if numeric {
+            run.parse().map(Token::Number).unwrap_or(Token::Alpha(run))
+        } else {
+            KEYWORDS
+                .iter()
+                .find(|(k, _)| run.eq_ignore_ascii_case(k))
+                .map(|(_, rank)| Token::Keyword(*rank))
+                .unwrap_or(Token::Alpha(run))
+        });
+        rest = tail;
+    }
+    tokens
+}
+
+/// An upstream version string ordered the way pisi orders them
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Version(pub String);
+
+impl Version {
+    pub fn new(version: impl Into<String>) -> Self {
+        Self(version.into())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (ours, theirs) = (tokens(&self.0), tokens(&other.0));
+        for (a, b) in ours.iter().zip(theirs.iter()) {
+            match a.cmp(b) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+
+        // A trailing pre-release suffix makes the longer version older
+        match ours.len().cmp(&theirs.len()) {
+            Ordering::Greater if ours[theirs.len()].is_prerelease() => Ordering::Less,
+            Ordering::Less if theirs[ours.len()].is_prerelease() => Ordering::Greater,
+            ordering => ordering,
+        }
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A version together with its packaging release
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Release {
+    pub version: Version,
+    pub release: u64,
+}
+
+impl Update {
+    pub fn to_release(&self) -> Release {
+        Release {
+            version: Version::new(self.version.clone()),
+            release: self.release,
+        }
+    }
+}
+
+impl History {
+    /// The most recent update, by release number and then by version so
+    /// the order of the index does not matter
+    pub fn latest(&self) -> Option<&Update> {
+        self.updates.iter().max_by(|a, b| {
+            a.release
+                .cmp(&b.release)
+                .then_with(|| a.to_release().cmp(&b.to_release()))
+        })
+    }
+}
+
+impl Constraint {
+    /// Whether a package at `candidate` fulfills the constraint
+    pub fn satisfied_by(&self, candidate: &Release) -> bool {
+        match self {
+            Constraint::Version(v) => candidate.version == Version::new(v),
+            Constraint::VersionFrom(v) => candidate.version >= Version::new(v),
+            Constraint::VersionTo(v) => candidate.version <= Version::new(v),
+            Constraint::Release(r) => candidate.release == *r,
+            Constraint::ReleaseFrom(r) => candidate.release >= *r,
+            Constraint::ReleaseTo(r) => candidate.release <= *r,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Release, Version};
+    use crate::eopkg::index::{Constraint, History, Update};
+
+    fn v(version: &str) -> Version {
+        Version::new(version)
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(v("1.10") > v("1.9"));
+        assert!(v("1.0") == v("1_0"));
+        assert!(v("1.0.1") > v("1.0"));
+        assert!(v("1.0rc1") < v("1.0"));
+        assert!(v("1.0_beta2") < v("1.0_rc1"));
+        assert!(v("1.0_alpha") < v("1.0_beta"));
+        assert!(v("1.0p1") > v("1.0"));
+        assert!(v("2.4a") > v("2.4"));
+        assert!(v("2.4b") > v("2.4a"));
+        assert!(v("0.0.26a") > v("0.0.26"));
+        assert!(v("20231010") > v("20230901"));
+    }
+
+    #[test]
+    fn constraints() {
+        let candidate = Release {
+            version: v("2.38"),
+            release: 90,
+        };
+        assert!(Constraint::ReleaseFrom(90).satisfied_by(&candidate));
+        assert!(!Constraint::Release(89).satisfied_by(&candidate));
+        assert!(Constraint::VersionFrom("2.9".into()).satisfied_by(&candidate));
+        assert!(!Constraint::VersionTo("2.37".into()).satisfied_by(&candidate));
+    }
+
+    #[test]
+    fn latest() {
+        let update = |version: &str, release| Update {
+            version: version.into(),
+            release,
+            ..Default::default()
+        };
+        let history = History {
+            updates: vec![update("1.2", 24), update("1.3", 26), update("1.3", 25)],
+        };
+        assert_eq!(history.latest().unwrap().release, 26);
+        assert!(History::default().latest().is_none());
+    }
+}
@@ -0,0 +1,690 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Fetching package archives into the local cache
+//!
+//! Transports implement [`Fetcher`], so the [`Downloader`] works the same
+//! against real mirrors via [`HttpFetcher`] or against a [`MockFetcher`].
+
+use std::{
+    collections::BTreeMap,
+    fs::{read_dir, remove_file, rename, File},
+    future::Future,
+    io::{Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use bytes::{Bytes, BytesMut};
+use crossterm::style::Stylize;
+use futures::future::try_join_all;
+use indicatif::ProgressBar;
+use reqwest::{header::RANGE, StatusCode};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, Take},
+    sync::mpsc,
+};
+use url::Url;
+
+use crate::{
+    converter::HashedPackage,
+    eopkg::index::Package,
+    http::{self, Client},
+    limit::RateLimit,
+    mirror::{self, Scheduler},
+};
+
+/// Number of times a package is downloaded before a hash mismatch is fatal
+const FETCH_ATTEMPTS: usize = 2;
+
+/// Chunks buffered between a download and its blocking writer
+const WRITE_QUEUE: usize = 32;
+
+/// Read size for local files
+const LOCAL_CHUNK: usize = 64 * 1024;
+
+/// Number of segments, and so connections, used for large archives
+const SEGMENTS: u64 = 4;
+
+/// Downloads are written under this extension until verified
+pub const PARTIAL_EXTENSION: &str = "part";
+
+/// SHA-256 digest and hex encoded SHA-1 digest of an archive
+type Digests = ([u8; 32], String);
+
+/// Body of a single transfer, read chunk by chunk
+pub trait Transfer: Send {
+    /// Length announced by the transport, if any
+    fn content_length(&self) -> Option<u64>;
+
+    /// Next chunk of the body, `None` once complete
+    fn chunk(&mut self) -> impl Future<Output = Result<Option<Bytes>, Error>> + Send;
+}
+
+/// Transport used to retrieve archives
+pub trait Fetcher: Sync {
+    type Transfer: Transfer;
+
+    /// Start transferring `uri`, or only the bytes within `range`
+    fn open(
+        &self,
+        uri: &Url,
+        range: Option<Range<u64>>,
+    ) -> impl Future<Output = Result<Self::Transfer, Error>> + Send;
+}
+
+/// Fetches over HTTP(S), or straight from disk for `file://` mirrors
+pub struct HttpFetcher {
+    client: Client,
+}
+
+impl HttpFetcher {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+pub enum HttpTransfer {
+    Remote {
+        client: Client,
+        response: reqwest::Response,
+    },
+    Local {
+        file: Take<tokio::fs::File>,
+        len: u64,
+    },
+}
+
+impl Transfer for HttpTransfer {
+    fn content_length(&self) -> Option<u64> {
+        match self {
+            Self::Remote { response, .. } => response.content_length(),
+            Self::Local { len, .. } => Some(*len),
+        }
+    }
+
+    async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        match self {
+            Self::Remote { client, response } => Ok(client.read(response.chunk()).await?),
+            Self::Local { file, .. } => {
+                let mut buffer = BytesMut::with_capacity(LOCAL_CHUNK);
+                let len = file.read_buf(&mut buffer).await?;
+                Ok((len > 0).then(|| buffer.freeze()))
+            }
+        }
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    type Transfer = HttpTransfer;
+
+    async fn open(&self, uri: &Url, range: Option<Range<u64>>) -> Result<HttpTransfer, Error> {
+        if uri.scheme() == "file" {
+            let path = uri.to_file_path().map_err(|_| Error::InvalidURI)?;
+            let mut file = tokio::fs::File::open(path).await?;
+            let size = file.metadata().await?.len();
+            let range = range.unwrap_or(0..size);
+            file.seek(SeekFrom::Start(range.start)).await?;
+            let len = range.end.min(size).saturating_sub(range.start);
+            return Ok(HttpTransfer::Local {
+                file: file.take(len),
+                len,
+            });
+        }
+
+        let mut request = self.client.get(uri.clone());
+        if let Some(range) = &range {
+            request = request.header(RANGE, format!("bytes={}-{}", range.start, range.end - 1));
+        }
+        let response = self.client.read(request.send()).await?.error_for_status()?;
+        if range.is_some() && response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(Error::RangeUnsupported(uri.to_string()));
+        }
+
+        Ok(HttpTransfer::Remote {
+            client: self.client.clone(),
+            response,
+        })
+    }
+}
+
+/// In-memory transport serving fixed payloads, for tests
+#[derive(Debug, Default)]
+pub struct MockFetcher {
+    files: BTreeMap<Url, Bytes>,
+
+    /// Ranges requested so far, `None` for whole transfers
+    pub requests: Mutex<Vec<Option<Range<u64>>>>,
+}
+
+impl MockFetcher {
+    /// Serve `data` for `uri`
+    pub fn insert(&mut self, uri: Url, data: impl Into<Bytes>) {
+        self.files.insert(uri, data.into());
+    }
+}
+
+pub struct MockTransfer {
+    data: Option<Bytes>,
+    len: u64,
+}
+
+impl Transfer for MockTransfer {
+    fn content_length(&self) -> Option<u64> {
+        Some(self.len)
+    }
+
+    async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        Ok(self.data.take())
+    }
+}
+
+impl Fetcher for MockFetcher {
+    type Transfer = MockTransfer;
+
+    async fn open(&self, uri: &Url, range: Option<Range<u64>>) -> Result<MockTransfer, Error> {
+        self.requests.lock().expect("poisoned").push(range.clone());
+        let mut data = self
+            .files
+            .get(uri)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(uri.to_string()))?;
+        if let Some(range) = range {
+            let end = (range.end as usize).min(data.len());
+            data = data.slice((range.start as usize).min(end)..end);
+        }
+        Ok(MockTransfer {
+            len: data.len() as u64,
+            data: Some(data),
+        })
+    }
+}
+
+/// Name of the archive for `p` within the cache
+pub fn file_name(p: &Package) -> Result<&str, Error> {
+    p.package_uri
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .ok_or(Error::InvalidURI)
+}
+
+/// Hash the body of `uri` as it streams in, without storing it. Used for
+/// upstream source archives, which are only referenced by recipes.
+///
+/// Bodies not matching the `sha1sum` the archive is published with are
+/// rejected.
+pub async fn hash_remote<F: Fetcher>(
+    fetcher: &F,
+    uri: &Url,
+    sha1sum: Option<&str>,
+    limit: Option<&RateLimit>,
+) -> Result<Digests, Error> {
+    let mut transfer = fetcher.open(uri, None).await?;
+    let mut hasher = Sha256::new();
+    let mut sha1 = Sha1::new();
+    while let Some(chunk) = transfer.chunk().await? {
+        hasher.update(&chunk);
+        sha1.update(&chunk);
+        if let Some(limit) = limit {
+            limit.consume(chunk.len() as u64).await;
+        }
+    }
+
+    let sha1 = const_hex::encode(sha1.finalize());
+    if let Some(expected) = sha1sum {
+        if !sha1.eq_ignore_ascii_case(expected) {
+            return Err(Error::HashMismatch {
+                path: uri.to_string(),
+                expected: expected.to_string(),
+                actual: sha1,
+            });
+        }
+    }
+
+    Ok((hasher.finalize().into(), sha1))
+}
+
+/// Hash a file, returning the SHA-256 digest and the hex encoded SHA-1
+pub fn hash_file(path: &Path) -> Result<Digests, Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut sha1 = Sha1::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buffer[..len]);
+        sha1.update(&buffer[..len]);
+    }
+
+    Ok((hasher.finalize().into(), const_hex::encode(sha1.finalize())))
+}
+
+/// Remove downloads that were interrupted before completion
+pub fn remove_partial(cache_dir: &Path) -> Result<(), Error> {
+    for entry in read_dir(cache_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == PARTIAL_EXTENSION) {
+            remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fetches verified packages into the cache
+pub struct Downloader<'a, F> {
+    pub fetcher: &'a F,
+    pub scheduler: &'a Scheduler,
+    pub cache_dir: &'a Path,
+    pub limit: Option<&'a RateLimit>,
+
+    /// Archives of at least this size are fetched in segments
+    pub segment_threshold: u64,
+
+    /// Aggregate progress across all transfers, in bytes
+    pub transferred: Option<&'a ProgressBar>,
+}
+
+impl<F: Fetcher> Downloader<'_, F> {
+    /// Location of the archive for `p` in the cache
+    pub fn cache_path(&self, p: &Package) -> Result<PathBuf, Error> {
+        Ok(self.cache_dir.join(file_name(p)?))
+    }
+
+    /// Reuse a cached download when it matches the index size and hash
+    pub async fn cached(&self, p: &Package) -> Result<Option<HashedPackage>, Error> {
+        let path = self.cache_path(p)?;
+        match path.metadata() {
+            Ok(meta) if meta.len() == p.package_size => {}
+            _ => return Ok(None),
+        }
+
+        let (hash, sha1) = tokio::task::spawn_blocking(move || hash_file(&path)).await??;
+        if !sha1.eq_ignore_ascii_case(&p.package_hash) {
+            return Ok(None);
+        }
+
+        Ok(Some(HashedPackage {
+            package: p.clone(),
+            hash,
+            files: None,
+            payload: None,
+        }))
+    }
+
+    /// Download `p` into the cache, verifying its size and hash against the
+    /// index. Progress is reported on `pbar`.
+    pub async fn download(&self, p: &Package, pbar: &ProgressBar) -> Result<HashedPackage, Error> {
+        let name = file_name(p)?;
+        let output_path = self.cache_dir.join(name);
+        let partial_path = self.cache_dir.join(format!("{name}.{PARTIAL_EXTENSION}"));
+
+        // Corrupt downloads are retried before giving up on the package
+        let mut attempt = 1;
+        let hash = loop {
+            // Failed transfers never leave their partial file in the cache
+            let (hash, sha1) = match self.fetch_archive(p, &partial_path, pbar).await {
+                Ok(digests) => digests,
+                Err(e) => {
+                    let _ = remove_file(&partial_path);
+                    return Err(e);
+                }
+            };
+            if sha1.eq_ignore_ascii_case(&p.package_hash) {
+                rename(&partial_path, &output_path)?;
+                break hash;
+            }
+
+            remove_file(&partial_path)?;
+            if attempt >= FETCH_ATTEMPTS {
+                return Err(Error::HashMismatch {
+                    path: name.to_string(),
+                    expected: p.package_hash.clone(),
+                    actual: sha1,
+                });
+            }
+            pbar.println(format!("{} {}, retrying", "Corrupt".yellow(), name.bold()));
+            pbar.reset();
+            attempt += 1;
+        };
+
+        Ok(HashedPackage {
+            package: p.clone(),
+            hash,
+            files: None,
+            payload: None,
+        })
+    }
+
+    /// Fetch the archive for `p` into `output_path`, in parallel segments
+    /// when it is large enough and the mirrors support ranged requests
+    async fn fetch_archive(
+        &self,
+        p: &Package,
+        output_path: &Path,
+        pbar: &ProgressBar,
+    ) -> Result<Digests, Error> {
+        if p.package_size >= self.segment_threshold {
+            match self.fetch_segmented(p, output_path, pbar).await {
+                Err(Error::RangeUnsupported(_)) => pbar.reset(),
+                result => return result,
+            }
+        }
+
+        // Hold a connection slot on the chosen mirror for the whole transfer
+        let (mirror, _permit) = self.scheduler.acquire().await?;
+        let uri = p.uri(mirror)?;
+        let output = File::create(output_path)?;
+        let digests = self
+            .transfer(&uri, None, p.package_size, output, pbar)
+            .await?;
+        Ok(digests.expect("whole transfers are hashed"))
+    }
+
+    /// Fetch `SEGMENTS` ranges of the archive concurrently, each on its own
+    /// connection slot, and hash the reassembled file as a whole
+    async fn fetch_segmented(
+        &self,
+        p: &Package,
+        output_path: &Path,
+        pbar: &ProgressBar,
+    ) -> Result<Digests, Error> {
+        File::create(output_path)?.set_len(p.package_size)?;
+
+        let segment_size = p.package_size.div_ceil(SEGMENTS);
+        let segments = (0..SEGMENTS)
+            .map(|i| i * segment_size..((i + 1) * segment_size).min(p.package_size))
+            .filter(|range| !range.is_empty())
+            .map(|range| {
+                // Each segment needs its own handle, clones share the cursor
+                let output = File::options().write(true).open(output_path);
+                async move {
+                    let (mirror, _permit) = self.scheduler.acquire().await?;
+                    let uri = p.uri(mirror)?;
+                    let expected = range.end - range.start;
+                    self.transfer(&uri, Some(range), expected, output?, pbar)
+                        .await
+                }
+            });
+        try_join_all(segments).await?;
+
+        let path = output_path.to_path_buf();
+        tokio::task::spawn_blocking(move || hash_file(&path)).await?
+    }
+
+    /// Transfer `uri`, or just `range` of it, into `output` at the matching
+    /// offset. Whole transfers return the digests of the transferred bytes,
+    /// segments are left for the caller to hash once reassembled.
+    ///
+    /// Transfers not matching the `expected` size are rejected as truncated
+    async fn transfer(
+        &self,
+        uri: &Url,
+        range: Option<Range<u64>>,
+        expected: u64,
+        mut output: File,
+        pbar: &ProgressBar,
+    ) -> Result<Option<Digests>, Error> {
+        let offset = range.as_ref().map(|r| r.start);
+        let mut transfer = self.fetcher.open(uri, range).await?;
+        if let Some(actual) = transfer.content_length() {
+            if actual != expected {
+                return Err(Error::SizeMismatch {
+                    uri: uri.to_string(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        // Writing and hashing happen on a blocking thread so CPU work never
+        // holds up the network reads feeding it
+        let (sender, mut receiver) = mpsc::channel::<Bytes>(WRITE_QUEUE);
+        let writer = tokio::task::spawn_blocking(move || -> Result<Option<Digests>, Error> {
+            let mut hashers = match offset {
+                Some(offset) => {
+                    output.seek(SeekFrom::Start(offset))?;
+                    None
+                }
+                None => Some((Sha256::new(), Sha1::new())),
+            };
+            while let Some(chunk) = receiver.blocking_recv() {
+                output.write_all(&chunk)?;
+                if let Some((hasher, sha1)) = &mut hashers {
+                    hasher.update(&chunk);
+                    sha1.update(&chunk);
+                }
+            }
+            Ok(hashers.map(|(hasher, sha1)| {
+                (hasher.finalize().into(), const_hex::encode(sha1.finalize()))
+            }))
+        });
+
+        let mut received = 0u64;
+        while let Some(chunk) = transfer.chunk().await? {
+            let len = chunk.len() as u64;
+            pbar.inc(len);
+            if let Some(transferred) = self.transferred {
+                transferred.inc(len);
+            }
+            received += len;
+            // The writer only hangs up after failing, which is reported below
+            if sender.send(chunk).await.is_err() {
+                break;
+            }
+            if let Some(limit) = self.limit {
+                limit.consume(len).await;
+            }
+        }
+        drop(sender);
+        let digests = writer.await??;
+
+        if received != expected {
+            return Err(Error::SizeMismatch {
+                uri: uri.to_string(),
+                expected,
+                actual: received,
+            });
+        }
+
+        Ok(digests)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("uri parse: {0}")]
+    URI(#[from] url::ParseError),
+
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("http: {0}")]
+    Http(#[from] http::Error),
+
+    #[error("mirror: {0}")]
+    Mirror(#[from] mirror::Error),
+
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("join: {0}")]
+    Join(#[from] tokio::task::JoinError),
+
+    #[error("invalid uri")]
+    InvalidURI,
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("ranged requests unsupported for {0}")]
+    RangeUnsupported(String),
+
+    #[error("size mismatch for {uri}: expected {expected} bytes, got {actual}")]
+    SizeMismatch {
+        uri: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("hash mismatch for {path}: expected {expected}, got {actual}")]
+    HashMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{create_dir_all, read, remove_dir_all};
+
+    use indicatif::ProgressBar;
+    use sha1::Sha1;
+    use sha2::{Digest, Sha256};
+    use url::Url;
+
+    use super::{hash_remote, Downloader, Error, MockFetcher};
+    use crate::{eopkg::index::Package, mirror::Scheduler};
+
+    fn package(data: &[u8]) -> Package {
+        Package {
+            name: "zlib".into(),
+            package_uri: "z/zlib/zlib-1.3-26-1-x86_64.eopkg".into(),
+            package_size: data.len() as u64,
+            package_hash: const_hex::encode(Sha1::digest(data)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn mock_download() {
+        let origin = Url::parse("https://mirror.example/unstable/").unwrap();
+        let data = b"not really a zip archive".to_vec();
+        let pkg = package(&data);
+
+        // Same payload published under an index entry that doesn't match it
+        let corrupt = Package {
+            package_uri: "z/zlib/corrupt.eopkg".into(),
+            package_hash: "0".repeat(40),
+            ..pkg.clone()
+        };
+
+        // Index entry claiming more data than the mirror serves
+        let truncated = Package {
+            package_uri: "z/zlib/truncated.eopkg".into(),
+            package_size: data.len() as u64 + 1,
+            ..pkg.clone()
+        };
+
+        let mut fetcher = MockFetcher::default();
+        fetcher.insert(origin.join(&pkg.package_uri).unwrap(), data.clone());
+        fetcher.insert(origin.join(&corrupt.package_uri).unwrap(), data.clone());
+        fetcher.insert(origin.join(&truncated.package_uri).unwrap(), data.clone());
+
+        let cache_dir = std::env::temp_dir().join(format!("pisi-fetch-{}", std::process::id()));
+        create_dir_all(&cache_dir).unwrap();
+        let scheduler = Scheduler::new(&[origin], 1);
+        let downloader = Downloader {
+            fetcher: &fetcher,
+            scheduler: &scheduler,
+            cache_dir: &cache_dir,
+            limit: None,
+            segment_threshold: u64::MAX,
+            transferred: None,
+        };
+
+        assert!(downloader.cached(&pkg).await.unwrap().is_none());
+        let fetched = downloader
+            .download(&pkg, &ProgressBar::hidden())
+            .await
+            .unwrap();
+        assert_eq!(fetched.hash, <[u8; 32]>::from(Sha256::digest(&data)));
+
+        // Second run is served from the cache
+        let cached = downloader.cached(&pkg).await.unwrap().expect("not cached");
+        assert_eq!(cached.hash, fetched.hash);
+
+        let fetched = downloader.download(&corrupt, &ProgressBar::hidden()).await;
+        assert!(matches!(fetched, Err(Error::HashMismatch { .. })));
+
+        let fetched = downloader
+            .download(&truncated, &ProgressBar::hidden())
+            .await;
+        assert!(matches!(fetched, Err(Error::SizeMismatch { .. })));
+        assert!(!cache_dir.join("truncated.eopkg.part").exists());
+
+        remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn segmented_download() {
+        let origin = Url::parse("https://mirror.example/unstable/").unwrap();
+        let data = (0..=255u8).cycle().take(4099).collect::<Vec<_>>();
+        let pkg = package(&data);
+
+        let mut fetcher = MockFetcher::default();
+        fetcher.insert(origin.join(&pkg.package_uri).unwrap(), data.clone());
+
+        let cache_dir = std::env::temp_dir().join(format!("pisi-segments-{}", std::process::id()));
+        create_dir_all(&cache_dir).unwrap();
+        let scheduler = Scheduler::new(&[origin], 4);
+        let downloader = Downloader {
+            fetcher: &fetcher,
+            scheduler: &scheduler,
+            cache_dir: &cache_dir,
+            limit: None,
+            segment_threshold: 1024,
+            transferred: None,
+        };
+
+        let fetched = downloader
+            .download(&pkg, &ProgressBar::hidden())
+            .await
+            .unwrap();
+        assert_eq!(fetched.hash, <[u8; 32]>::from(Sha256::digest(&data)));
+        let cached = read(downloader.cache_path(&pkg).unwrap()).unwrap();
+        assert_eq!(cached, data);
+
+        let mut requests = fetcher.requests.into_inner().unwrap();
+        requests.sort_by_key(|range| range.as_ref().map(|r| r.start));
+        assert_eq!(
+            requests,
+            [
+                Some(0..1025),
+                Some(1025..2050),
+                Some(2050..3075),
+                Some(3075..4099)
+            ]
+        );
+
+        remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn remote_digests() {
+        let uri = Url::parse("https://www.nano-editor.org/dist/v7/nano-7.2.tar.xz").unwrap();
+        let data = b"not really a tarball".to_vec();
+        let mut fetcher = MockFetcher::default();
+        fetcher.insert(uri.clone(), data.clone());
+
+        let expected = const_hex::encode(Sha1::digest(&data));
+        let (hash, sha1) = hash_remote(&fetcher, &uri, Some(&expected), None)
+            .await
+            .unwrap();
+        assert_eq!(hash, <[u8; 32]>::from(Sha256::digest(&data)));
+        assert_eq!(sha1, expected);
+
+        let hashed = hash_remote(&fetcher, &uri, Some(&"0".repeat(40)), None).await;
+        assert!(matches!(hashed, Err(Error::HashMismatch { .. })));
+    }
+}
@@ -0,0 +1,777 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Dependency graph of the resolved packages, and its exports
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Display,
+    str::FromStr,
+};
+
+use petgraph::{
+    algo::tarjan_scc,
+    stable_graph::{NodeIndex, StableDiGraph},
+    Direction,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::eopkg::{
+    index::{Package, DEFAULT_LANGUAGE},
+    name::PackageName,
+};
+
+/// Format the graph is exported in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Graphviz DOT
+    #[default]
+    Dot,
+    /// Adjacency list of the nodes and their metadata
+    Json,
+    /// Mermaid flowchart, as rendered in issue discussions
+    Mermaid,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Format::Dot),
+            "json" => Ok(Format::Json),
+            "mermaid" => Ok(Format::Mermaid),
+            _ => Err(Error::InvalidFormat(s.to_string())),
+        }
+    }
+}
+
+/// Granularity of the nodes of an exported graph
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Level {
+    /// One node per binary package
+    #[default]
+    Package,
+    /// One node per recipe, which is what gets built
+    Source,
+}
+
+impl FromStr for Level {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "package" => Ok(Level::Package),
+            "source" => Ok(Level::Source),
+            _ => Err(Error::InvalidLevel(s.to_string())),
+        }
+    }
+}
+
+/// Index metadata of a package in the JSON export
+#[derive(Debug, Clone, Serialize)]
+pub struct Metadata {
+    pub source: String,
+    pub version: Option<String>,
+    pub release: Option<u64>,
+    pub component: Option<String>,
+    pub summary: String,
+    pub package_size: u64,
+    pub installed_size: u64,
+}
+
+impl From<&Package> for Metadata {
+    fn from(package: &Package) -> Self {
+        let latest = package.history.latest();
+        Self {
+            source: package.source.name.to_string(),
+            version: latest.map(|u| u.version.clone()),
+            release: latest.map(|u| u.release),
+            component: package.part_of.clone(),
+            summary: package.summary.get(DEFAULT_LANGUAGE).to_string(),
+            package_size: package.package_size,
+            installed_size: package.installed_size,
+        }
+    }
+}
+
+/// Node of the JSON export, along with its dependencies
+#[derive(Debug, Serialize)]
+struct Node<'a, N, M> {
+    name: &'a N,
+    #[serde(flatten)]
+    metadata: Option<M>,
+    deps: Vec<&'a N>,
+}
+
+/// Nodes with an edge to each of their run dependencies, backed by a
+/// petgraph graph. Nodes and edges iterate in order, whatever the order
+/// they were added in.
+#[derive(Debug, Clone)]
+pub struct Graph<N> {
+    graph: StableDiGraph<N, ()>,
+    indices: BTreeMap<N, NodeIndex>,
+}
+
+impl<N> Default for Graph<N> {
+    fn default() -> Self {
+        Self {
+            graph: StableDiGraph::new(),
+            indices: BTreeMap::new(),
+        }
+    }
+}
+
+impl<N: Ord + Clone> Graph<N> {
+    pub fn add_node(&mut self, node: N) {
+        self.index(node);
+    }
+
+    /// Record that `from` depends on `to`, adding either when missing
+    pub fn add_edge(&mut self, from: N, to: N) {
+        let (from, to) = (self.index(from), self.index(to));
+        self.graph.update_edge(from, to, ());
+    }
+
+    /// Remove `node` along with the edges from and to it
+    pub fn remove_node(&mut self, node: &N) {
+        if let Some(index) = self.indices.remove(node) {
+            self.graph.remove_node(index);
+        }
+    }
+
+    pub fn contains(&self, node: &N) -> bool {
+        self.indices.contains_key(node)
+    }
+
+    /// Nodes in order
+    pub fn nodes(&self) -> impl Iterator<Item = &N> {
+        self.indices.keys()
+    }
+
+    /// Direct dependencies of `node`, in order
+    pub fn deps(&self, node: &N) -> impl Iterator<Item = &N> {
+        self.neighbors(node, Direction::Outgoing)
+    }
+
+    /// Direct dependents of `node`, in order
+    pub fn dependents(&self, node: &N) -> impl Iterator<Item = &N> {
+        self.neighbors(node, Direction::Incoming)
+    }
+
+    /// Every `(dependent, dependency)` pair, in order
+    pub fn edges(&self) -> impl Iterator<Item = (&N, &N)> {
+        self.nodes()
+            .flat_map(|from| self.deps(from).map(move |to| (from, to)))
+    }
+
+    /// The same nodes with every edge turned around, from a dependency to
+    /// its dependents
+    pub fn reverse(&self) -> Self {
+        let mut reversed = self.clone();
+        reversed.graph.reverse();
+        reversed
+    }
+
+    /// Nodes `node` depends on through one or more edges
+    pub fn descendants(&self, node: &N) -> BTreeSet<N> {
+        self.reachable(node, Direction::Outgoing)
+    }
+
+    /// Nodes depending on `node` through one or more edges
+    pub fn ancestors(&self, node: &N) -> BTreeSet<N> {
+        self.reachable(node, Direction::Incoming)
+    }
+
+    /// The part of the graph reachable from `roots`, including them
+    pub fn subgraph(&self, roots: &[N]) -> Self {
+        let closure = self.closure(roots, None);
+        let mut subgraph = Self::default();
+        for node in closure.iter() {
+            subgraph.add_node((*node).clone());
+            for dep in self.deps(node) {
+                subgraph.add_edge((*node).clone(), dep.clone());
+            }
+        }
+        subgraph
+    }
+
+    /// The graph with nodes collapsed onto what `f` maps them to, such as
+    /// their source. Edges within one node and nodes mapped to `None` are
+    /// left out.
+    pub fn map<M: Ord + Clone>(&self, f: impl Fn(&N) -> Option<M>) -> Graph<M> {
+        let mut mapped = Graph::default();
+        for node in self.nodes() {
+            let Some(from) = f(node) else {
+                continue;
+            };
+            mapped.add_node(from.clone());
+            for to in self.deps(node).filter_map(&f) {
+                if to != from {
+                    mapped.add_edge(from.clone(), to);
+                }
+            }
+        }
+        mapped
+    }
+
+    /// Nodes ordered after all of their dependencies. Members of a cycle
+    /// are next to each other, in order.
+    pub fn topo(&self) -> Vec<N> {
+        self.components().into_iter().flatten().cloned().collect()
+    }
+
+    /// Groups of nodes that can be built in parallel, each only depending
+    /// on nodes of earlier groups. Members of a cycle share a group.
+    pub fn waves(&self) -> Vec<Vec<N>> {
+        let mut waves: Vec<Vec<N>> = vec![];
+        let mut wave_of = BTreeMap::new();
+        for component in self.components() {
+            let wave = component
+                .iter()
+                .flat_map(|node| self.deps(node))
+                .filter(|dep| !component.contains(dep))
+                .map(|dep| wave_of[dep] + 1)
+                .max()
+                .unwrap_or(0);
+            if waves.len() <= wave {
+                waves.resize(wave + 1, vec![]);
+            }
+            for node in component {
+                wave_of.insert(node, wave);
+                waves[wave].push(node.clone());
+            }
+        }
+        for wave in waves.iter_mut() {
+            wave.sort();
+        }
+        waves
+    }
+
+    /// Groups of nodes that all depend on each other, found as the
+    /// strongly connected components with more than one node or a node
+    /// depending on itself. Members are in order, as are the groups.
+    pub fn cycles(&self) -> Vec<Vec<N>> {
+        let mut cycles = self
+            .components()
+            .into_iter()
+            .filter(|c| c.len() > 1 || self.deps(c[0]).any(|d| d == c[0]))
+            .map(|c| c.into_iter().cloned().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        cycles.sort();
+        cycles
+    }
+
+    /// Fewest edges leading from any of `roots` to `target`, starting with
+    /// the root, or `None` when it is not reachable
+    pub fn shortest_path(&self, roots: &[N], target: &N) -> Option<Vec<N>> {
+        let mut parents = BTreeMap::<&N, Option<&N>>::new();
+        let mut pending = VecDeque::new();
+        for root in roots.iter().filter(|r| self.contains(r)) {
+            if !parents.contains_key(root) {
+                parents.insert(root, None);
+                pending.push_back(root);
+            }
+        }
+        while let Some(node) = pending.pop_front() {
+            if node == target {
+                let mut path = vec![node.clone()];
+                let mut current = node;
+                while let Some(&Some(parent)) = parents.get(current) {
+                    path.push(parent.clone());
+                    current = parent;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for dep in self.deps(node) {
+                if !parents.contains_key(dep) {
+                    parents.insert(dep, Some(node));
+                    pending.push_back(dep);
+                }
+            }
+        }
+        None
+    }
+
+    /// Heaviest chain of dependencies from each of `roots`, by the total
+    /// `weight` of its nodes, heaviest first. Members of a cycle are weighed
+    /// together and listed in order.
+    pub fn heaviest_chains(&self, roots: &[N], weight: impl Fn(&N) -> u64) -> Vec<(u64, Vec<N>)> {
+        let components = self.components();
+        let component_of = component_indices(&components);
+
+        // Components come out after every component they depend on
+        let mut heaviest: Vec<(u64, Option<usize>)> = vec![];
+        for (i, component) in components.iter().enumerate() {
+            let own = component.iter().map(|n| weight(n)).sum::<u64>();
+            let next = component
+                .iter()
+                .flat_map(|node| self.deps(node))
+                .map(|dep| component_of[dep])
+                .filter(|j| *j != i)
+                .max_by_key(|j| (heaviest[*j].0, Reverse(*j)));
+            heaviest.push((own + next.map_or(0, |j| heaviest[j].0), next));
+        }
+
+        let starts = roots
+            .iter()
+            .filter_map(|root| component_of.get(root).copied())
+            .collect::<BTreeSet<_>>();
+        let mut chains = starts
+            .into_iter()
+            .map(|start| {
+                let mut chain = vec![];
+                let mut next = Some(start);
+                while let Some(i) = next {
+                    chain.extend(components[i].iter().copied().cloned());
+                    next = heaviest[i].1;
+                }
+                (heaviest[start].0, chain)
+            })
+            .collect::<Vec<_>>();
+        chains.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        chains
+    }
+
+    /// Weight each node of the closure of `roots` would save when excluded,
+    /// its own along with that of the nodes only it pulls in, largest first
+    pub fn savings(&self, roots: &[N], weight: impl Fn(&N) -> u64) -> Vec<(N, u64)> {
+        let closure = self.closure(roots, None);
+        let total = closure.iter().map(|n| weight(n)).sum::<u64>();
+        let mut savings = closure
+            .iter()
+            .map(|excluded| {
+                let kept = self.closure(roots, Some(*excluded));
+                let kept = kept.iter().map(|n| weight(n)).sum::<u64>();
+                ((*excluded).clone(), total - kept)
+            })
+            .collect::<Vec<_>>();
+        savings.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        savings
+    }
+
+    /// Strongly connected components, each after the ones it depends on and
+    /// otherwise in order by their first member, which they list in order
+    pub fn components(&self) -> Vec<Vec<&N>> {
+        let mut components = tarjan_scc(&self.graph)
+            .into_iter()
+            .map(|component| {
+                let mut members = component
+                    .into_iter()
+                    .map(|i| &self.graph[i])
+                    .collect::<Vec<_>>();
+                members.sort();
+                members
+            })
+            .collect::<Vec<_>>();
+        let component_of = component_indices(&components);
+
+        // Order the condensed graph, taking the first ready component each time
+        let mut remaining = vec![0; components.len()];
+        let mut dependents = vec![BTreeSet::new(); components.len()];
+        for (i, component) in components.iter().enumerate() {
+            let deps = component
+                .iter()
+                .flat_map(|node| self.deps(node))
+                .map(|dep| component_of[dep])
+                .filter(|j| *j != i)
+                .collect::<BTreeSet<_>>();
+            remaining[i] = deps.len();
+            for j in deps {
+                dependents[j].insert(i);
+            }
+        }
+        let mut ready = (0..components.len())
+            .filter(|i| remaining[*i] == 0)
+            .map(|i| (components[i][0], i))
+            .collect::<BTreeSet<_>>();
+        let mut order = vec![];
+        while let Some((_, i)) = ready.pop_first() {
+            order.push(i);
+            for &j in dependents[i].iter() {
+                remaining[j] -= 1;
+                if remaining[j] == 0 {
+                    ready.insert((components[j][0], j));
+                }
+            }
+        }
+        order
+            .into_iter()
+            .map(|i| std::mem::take(&mut components[i]))
+            .collect()
+    }
+
+    fn index(&mut self, node: N) -> NodeIndex {
+        match self.indices.get(&node) {
+            Some(index) => *index,
+            None => {
+                let index = self.graph.add_node(node.clone());
+                self.indices.insert(node, index);
+                index
+            }
+        }
+    }
+
+    fn neighbors(&self, node: &N, direction: Direction) -> impl Iterator<Item = &N> {
+        let mut neighbors = match self.indices.get(node) {
+            Some(index) => self
+                .graph
+                .neighbors_directed(*index, direction)
+                .map(|i| &self.graph[i])
+                .collect(),
+            None => vec![],
+        };
+        neighbors.sort();
+        neighbors.into_iter()
+    }
+
+    /// Nodes reachable from `node` through one or more edges in `direction`
+    fn reachable(&self, node: &N, direction: Direction) -> BTreeSet<N> {
+        let mut seen = BTreeSet::new();
+        let mut pending = self.neighbors(node, direction).collect::<Vec<_>>();
+        while let Some(next) = pending.pop() {
+            if seen.insert(next.clone()) {
+                pending.extend(self.neighbors(next, direction));
+            }
+        }
+        seen
+    }
+
+    /// Nodes reachable from `roots`, including them, without passing
+    /// through `excluded`
+    fn closure<'a>(&'a self, roots: &'a [N], excluded: Option<&N>) -> BTreeSet<&'a N> {
+        let mut seen = BTreeSet::new();
+        let mut pending = roots
+            .iter()
+            .filter(|r| self.contains(r))
+            .collect::<Vec<_>>();
+        while let Some(next) = pending.pop() {
+            if Some(next) != excluded && seen.insert(next) {
+                pending.extend(self.deps(next));
+            }
+        }
+        seen
+    }
+}
+
+/// Position of the component each node is a member of
+fn component_indices<'a, N: Ord>(components: &[Vec<&'a N>]) -> BTreeMap<&'a N, usize> {
+    let mut indices = BTreeMap::new();
+    for (i, component) in components.iter().enumerate() {
+        for node in component {
+            indices.insert(*node, i);
+        }
+    }
+    indices
+}
+
+impl Graph<PackageName> {
+    /// Run dependencies among all of `packages`, such as a whole index
+    pub fn of_packages<'a>(packages: impl IntoIterator<Item = &'a Package>) -> Self {
+        let mut graph = Self::default();
+        for package in packages {
+            graph.add_node(package.name.clone());
+            for dep in package.run_deps.iter().flat_map(|d| d.deps.iter()) {
+                graph.add_edge(package.name.clone(), dep.value.clone());
+            }
+        }
+        graph
+    }
+}
+
+impl<N: Ord + Clone + Display> Graph<N> {
+    /// Graphviz DOT digraph called `name`
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut lines = vec![format!("digraph {} {{", quote(name))];
+        lines.push("  rankdir=LR;".to_string());
+        lines.push("  node [shape=box];".to_string());
+        for node in self.nodes() {
+            lines.push(format!("  {};", quote(node)));
+        }
+        for (from, to) in self.edges() {
+            lines.push(format!("  {} -> {};", quote(from), quote(to)));
+        }
+        lines.push("}".to_string());
+        lines.join("\n") + "\n"
+    }
+
+    /// Mermaid flowchart. Nodes get numbered ids, as names may contain
+    /// characters Mermaid reserves, and are labelled with their name.
+    pub fn to_mermaid(&self) -> String {
+        let ids = self
+            .nodes()
+            .enumerate()
+            .map(|(i, node)| (node, format!("n{i}")))
+            .collect::<BTreeMap<_, _>>();
+        let mut lines = vec!["graph LR".to_string()];
+        for (node, id) in ids.iter() {
+            let label = node.to_string().replace('"', "#quot;");
+            lines.push(format!("  {id}[\"{label}\"]"));
+        }
+        for (from, to) in self.edges() {
+            lines.push(format!("  {} --> {}", ids[from], ids[to]));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+impl<N: Ord + Clone + Serialize> Graph<N> {
+    /// JSON adjacency list, with the `metadata` of each node known to it
+    pub fn to_json<M: Serialize>(
+        &self,
+        metadata: impl Fn(&N) -> Option<M>,
+    ) -> Result<String, serde_json::Error> {
+        let nodes = self
+            .nodes()
+            .map(|node| Node {
+                name: node,
+                metadata: metadata(node),
+                deps: self.deps(node).collect(),
+            })
+            .collect::<Vec<_>>();
+        let mut json = serde_json::to_string_pretty(&serde_json::json!({ "nodes": nodes }))?;
+        json.push('\n');
+        Ok(json)
+    }
+}
+
+/// DOT identifier, quoted as names contain dashes and dots
+fn quote(id: impl Display) -> String {
+    format!(
+        "\"{}\"",
+        id.to_string().replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid graph format: {0}, expected dot, json or mermaid")]
+    InvalidFormat(String),
+
+    #[error("invalid graph level: {0}, expected package or source")]
+    InvalidLevel(String),
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::Graph;
+
+    #[test]
+    fn dot() {
+        let mut graph = Graph::default();
+        graph.add_edge("nano", "ncurses");
+        graph.add_edge("nano", "glibc");
+        graph.add_edge("ncurses", "glibc");
+        graph.add_node("file");
+
+        assert_eq!(
+            graph.deps(&"nano").collect::<Vec<_>>(),
+            [&"glibc", &"ncurses"]
+        );
+        assert_eq!(
+            graph.to_dot("system.base"),
+            "digraph \"system.base\" {\n  rankdir=LR;\n  node [shape=box];\n  \
+             \"file\";\n  \"glibc\";\n  \"nano\";\n  \"ncurses\";\n  \
+             \"nano\" -> \"glibc\";\n  \"nano\" -> \"ncurses\";\n  \"ncurses\" -> \"glibc\";\n}\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let mut graph = Graph::default();
+        graph.add_edge("nano", "ncurses");
+        graph.add_edge("nano", "glibc");
+
+        let json = graph
+            .to_json(|node| (*node == "nano").then_some(serde_json::json!({ "source": "nano" })))
+            .unwrap();
+        let json: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            json["nodes"][1],
+            serde_json::json!({ "name": "nano", "source": "nano", "deps": ["glibc", "ncurses"] })
+        );
+        assert_eq!(
+            json["nodes"][0],
+            serde_json::json!({ "name": "glibc", "deps": [] })
+        );
+    }
+
+    #[test]
+    fn dependents() {
+        let mut graph = Graph::default();
+        graph.add_edge("nano", "ncurses");
+        graph.add_edge("ncurses", "glibc");
+        graph.add_edge("file", "glibc");
+
+        let mut reversed = graph.reverse();
+        assert_eq!(
+            reversed.deps(&"glibc").collect::<Vec<_>>(),
+            [&"file", &"ncurses"]
+        );
+        assert_eq!(
+            reversed
+                .descendants(&"glibc")
+                .into_iter()
+                .collect::<Vec<_>>(),
+            ["file", "nano", "ncurses"]
+        );
+        assert!(reversed.descendants(&"nano").is_empty());
+
+        reversed.remove_node(&"ncurses");
+        assert!(!reversed.contains(&"ncurses"));
+        assert_eq!(reversed.descendants(&"glibc").len(), 1);
+    }
+
+    #[test]
+    fn subgraph() {
+        let mut graph = Graph::default();
+        graph.add_edge("nano", "ncurses");
+        graph.add_edge("ncurses", "glibc");
+        graph.add_edge("file", "glibc");
+        graph.add_edge("glibc", "libgcc");
+        graph.add_edge("libgcc", "glibc");
+
+        assert_eq!(
+            graph.ancestors(&"glibc").into_iter().collect::<Vec<_>>(),
+            ["file", "glibc", "libgcc", "nano", "ncurses"]
+        );
+        assert_eq!(
+            graph.dependents(&"glibc").collect::<Vec<_>>(),
+            [&"file", &"libgcc", &"ncurses"]
+        );
+
+        let subgraph = graph.subgraph(&["nano"]);
+        assert!(!subgraph.contains(&"file"));
+        assert_eq!(
+            subgraph
+                .descendants(&"nano")
+                .into_iter()
+                .collect::<Vec<_>>(),
+            ["glibc", "libgcc", "ncurses"]
+        );
+        assert_eq!(
+            subgraph.components(),
+            [vec![&"glibc", &"libgcc"], vec![&"ncurses"], vec![&"nano"]]
+        );
+    }
+
+    #[test]
+    fn cycles() {
+        let mut graph = Graph::default();
+        graph.add_edge("glibc", "libgcc");
+        graph.add_edge("libgcc", "glibc");
+        graph.add_edge("nano", "ncurses");
+        graph.add_edge("ncurses", "nano-syntax");
+        graph.add_edge("nano-syntax", "nano");
+        graph.add_edge("ncurses", "glibc");
+        graph.add_edge("bash", "bash");
+        graph.add_edge("file", "glibc");
+
+        assert_eq!(
+            graph.cycles(),
+            [
+                vec!["bash"],
+                vec!["glibc", "libgcc"],
+                vec!["nano", "nano-syntax", "ncurses"]
+            ]
+        );
+    }
+
+    #[test]
+    fn waves() {
+        let mut graph = Graph::default();
+        graph.add_edge("nano", "ncurses");
+        graph.add_edge("nano-devel", "nano");
+        graph.add_edge("ncurses", "glibc");
+        graph.add_edge("glibc", "libgcc");
+        graph.add_edge("libgcc", "glibc");
+        graph.add_edge("file", "glibc");
+        graph.add_node("zlib");
+
+        let sources = graph.map(|p| Some(p.trim_end_matches("-devel").to_string()));
+        assert_eq!(sources.cycles(), [vec!["glibc", "libgcc"]]);
+        assert_eq!(
+            sources.topo(),
+            ["glibc", "libgcc", "file", "ncurses", "nano", "zlib"]
+        );
+        assert_eq!(
+            sources.waves(),
+            [
+                vec!["glibc", "libgcc", "zlib"],
+                vec!["file", "ncurses"],
+                vec!["nano"]
+            ]
+        );
+    }
+
+    #[test]
+    fn weights() {
+        let mut graph = Graph::default();
+        graph.add_edge("nano", "ncurses");
+        graph.add_edge("nano", "file");
+        graph.add_edge("ncurses", "glibc");
+        graph.add_edge("file", "zlib");
+        graph.add_edge("zlib", "glibc");
+        graph.add_edge("vim", "ncurses");
+        let weight = |p: &&str| match *p {
+            "glibc" => 100,
+            "file" => 5,
+            "zlib" => 3,
+            "ncurses" => 10,
+            _ => 1,
+        };
+
+        assert_eq!(
+            graph.heaviest_chains(&["nano", "vim"], weight),
+            [
+                (111, vec!["nano", "ncurses", "glibc"]),
+                (111, vec!["vim", "ncurses", "glibc"])
+            ]
+        );
+        let savings = graph.savings(&["nano", "vim"], weight);
+        assert_eq!(savings[0], ("glibc", 100));
+        assert!(savings.contains(&("file", 8)));
+        assert!(savings.contains(&("ncurses", 10)));
+        assert!(savings.contains(&("vim", 1)));
+    }
+
+    #[test]
+    fn why() {
+        let mut graph = Graph::default();
+        graph.add_edge("mesalib", "llvm");
+        graph.add_edge("gtk3", "cairo");
+        graph.add_edge("cairo", "mesalib");
+        graph.add_edge("llvm", "zlib");
+        graph.add_node("nano");
+
+        assert_eq!(
+            graph.shortest_path(&["gtk3", "nano", "mesalib"], &"llvm"),
+            Some(vec!["mesalib", "llvm"])
+        );
+        assert_eq!(
+            graph.shortest_path(&["gtk3"], &"zlib"),
+            Some(vec!["gtk3", "cairo", "mesalib", "llvm", "zlib"])
+        );
+        assert_eq!(graph.shortest_path(&["nano"], &"nano"), Some(vec!["nano"]));
+        assert_eq!(graph.shortest_path(&["nano"], &"llvm"), None);
+    }
+
+    #[test]
+    fn mermaid() {
+        let mut graph = Graph::default();
+        graph.add_edge("nano", "ncurses");
+        graph.add_edge("ncurses", "glibc");
+
+        assert_eq!(
+            graph.to_mermaid(),
+            "graph LR\n  n0[\"glibc\"]\n  n1[\"nano\"]\n  n2[\"ncurses\"]\n  \
+             n1 --> n2\n  n2 --> n0\n"
+        );
+    }
+}
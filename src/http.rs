@@ -0,0 +1,105 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Shared HTTP client
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use reqwest::{NoProxy, Proxy, RequestBuilder};
+use thiserror::Error;
+use url::Url;
+
+use crate::config::Credential;
+
+/// Settings used when constructing the [`Client`]
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Explicit proxy, otherwise `HTTP_PROXY`/`HTTPS_PROXY` are honoured
+    pub proxy: Option<Url>,
+
+    /// Credentials attached to requests for matching hosts
+    pub credentials: Vec<Credential>,
+
+    /// Maximum time to establish a connection
+    pub connect_timeout: Option<Duration>,
+
+    /// Maximum time to wait on any single read, including response headers
+    pub read_timeout: Option<Duration>,
+}
+
+/// Cheaply cloneable client attaching per-host credentials
+#[derive(Clone)]
+pub struct Client {
+    inner: reqwest::Client,
+    credentials: Arc<[Credential]>,
+    read_timeout: Option<Duration>,
+}
+
+impl Client {
+    pub fn new(options: Options) -> Result<Self, Error> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(proxy) = options.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?.no_proxy(NoProxy::from_env()));
+        }
+        if let Some(timeout) = options.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        Ok(Self {
+            inner: builder.build()?,
+            credentials: options.credentials.into(),
+            read_timeout: options.read_timeout,
+        })
+    }
+
+    /// Await a single read such as `send()` or `chunk()`, failing when it
+    /// stalls for longer than the read timeout
+    pub async fn read<T>(
+        &self,
+        read: impl Future<Output = reqwest::Result<T>>,
+    ) -> Result<T, Error> {
+        match self.read_timeout {
+            Some(timeout) => Ok(tokio::time::timeout(timeout, read)
+                .await
+                .map_err(|_| Error::ReadTimeout(timeout))??),
+            None => Ok(read.await?),
+        }
+    }
+
+    /// Start a GET request for `uri`
+    pub fn get(&self, uri: Url) -> RequestBuilder {
+        let credential = self.credential(&uri).cloned();
+        let request = self.inner.get(uri);
+
+        match credential {
+            Some(Credential {
+                token: Some(token), ..
+            }) => request.bearer_auth(token),
+            Some(Credential {
+                username: Some(username),
+                password,
+                ..
+            }) => request.basic_auth(username, password),
+            _ => request,
+        }
+    }
+
+    /// Host specific credentials win over those applying to every host
+    fn credential(&self, uri: &Url) -> Option<&Credential> {
+        let host = uri.host_str()?;
+        self.credentials
+            .iter()
+            .find(|c| c.host.as_deref() == Some(host))
+            .or_else(|| self.credentials.iter().find(|c| c.host.is_none()))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("no data received for {0:?}")]
+    ReadTimeout(Duration),
+}
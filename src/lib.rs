@@ -2,5 +2,15 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+pub mod cache;
+pub mod config;
 pub mod converter;
 pub mod eopkg;
+pub mod fetch;
+pub mod graph;
+pub mod http;
+pub mod license;
+pub mod limit;
+pub mod mirror;
+pub mod recipe;
+pub mod schema;
@@ -0,0 +1,312 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Normalization of eopkg license strings to SPDX expressions
+
+use std::collections::BTreeMap;
+
+/// SPDX identifiers in use across the Solus repository
+const SPDX: &[&str] = &[
+    "0BSD",
+    "AGPL-1.0-or-later",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+    "AOMPL-1.0",
+    "Apache-2.0",
+    "Artistic-1.0",
+    "Artistic-1.0-Perl",
+    "Artistic-2.0",
+    "Beerware",
+    "BSD-1-Clause",
+    "BSD-2-Clause",
+    "BSD-2-Clause-FreeBSD",
+    "BSD-2-Clause-NetBSD",
+    "BSD-3-Clause",
+    "BSD-3-Clause-Attribution",
+    "BSD-4-Clause",
+    "BSD-4-Clause-UC",
+    "BSL-1.0",
+    "bzip2-1.0.6",
+    "CC-BY-2.0",
+    "CC-BY-2.5",
+    "CC-BY-3.0",
+    "CC-BY-4.0",
+    "CC-BY-ND-3.0",
+    "CC-BY-NC-SA-2.5",
+    "CC-BY-NC-SA-3.0",
+    "CC-BY-NC-SA-4.0",
+    "CC-BY-SA-2.5",
+    "CC-BY-SA-3.0",
+    "CC-BY-SA-4.0",
+    "CC-PDDC",
+    "CC0-1.0",
+    "CDDL-1.0",
+    "CECILL-2.1",
+    "CECILL-C",
+    "ClArtistic",
+    "CPAL-1.0",
+    "CPL-1.0",
+    "EPL-1.0",
+    "EPL-2.0",
+    "GFDL-1.1-only",
+    "GFDL-1.1-or-later",
+    "GFDL-1.2-only",
+    "GFDL-1.2-or-later",
+    "GFDL-1.3-only",
+    "GFDL-1.3-or-later",
+    "Giftware",
+    "GL2PS",
+    "gnuplot",
+    "GPL-1.0-only",
+    "GPL-1.0-or-later",
+    "GPL-2.0-only",
+    "GPL-2.0-or-later",
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "HPND",
+    "IJG",
+    "Info-ZIP",
+    "ISC",
+    "JasPer-2.0",
+    "LAL-1.3",
+    "LGPL-2.0-only",
+    "LGPL-2.0-or-later",
+    "LGPL-2.1-only",
+    "LGPL-2.1-or-later",
+    "LGPL-3.0-only",
+    "LGPL-3.0-or-later",
+    "Libpng",
+    "libtiff",
+    "LPL-1.02",
+    "LPPL-1.0",
+    "MIT",
+    "MIT-CMU",
+    "MIT-open-group",
+    "MPL-1.0",
+    "MPL-1.1",
+    "MPL-2.0",
+    "NASA-1.3",
+    "NCSA",
+    "OFL-1.0",
+    "OFL-1.1",
+    "OFL-1.1-RFN",
+    "OLDAP-2.8",
+    "OML",
+    "OpenSSL",
+    "OSL-2.1",
+    "PDDL-1.0",
+    "PHP-3.01",
+    "PostgreSQL",
+    "PSF-2.0",
+    "Python-2.0",
+    "QPL-1.0",
+    "Qhull",
+    "Rdisc",
+    "Ruby",
+    "SGI-B-1.0",
+    "SSPL-1.0",
+    "TCL",
+    "TCGL",
+    "Unicode-TOU",
+    "Unlicense",
+    "Vim",
+    "W3C",
+    "WTFPL",
+    "X11",
+    "Zlib",
+    "zlib-acknowledgement",
+    "ZPL-2.0",
+    "ZPL-2.1",
+];
+
+/// Deprecated identifiers, common shorthands and misspellings. Keys are
+/// matched ignoring case.
+const ALIASES: &[(&str, &str)] = &[
+    ("AGPL-3.0", "AGPL-3.0-only"),
+    ("AGPLv3", "AGPL-3.0-only"),
+    ("AGPLv3+", "AGPL-3.0-or-later"),
+    ("Apache", "Apache-2.0"),
+    (
+        "Apache-2.0-with-LLVM-exception",
+        "Apache-2.0 WITH LLVM-exception",
+    ),
+    (
+        "Apache-2.0-WITH-LLVM-Exception",
+        "Apache-2.0 WITH LLVM-exception",
+    ),
+    ("Artistic-Perl-1.0", "Artistic-1.0-Perl"),
+    ("Artisitic-1.0-Perl", "Artistic-1.0-Perl"),
+    ("BSD", "BSD-3-Clause"),
+    ("BSD-3", "BSD-3-Clause"),
+    ("BSD 3-Clause", "BSD-3-Clause"),
+    ("BSD-3 Clause", "BSD-3-Clause"),
+    ("BSD-3-Cluase", "BSD-3-Clause"),
+    ("BSD-3-Clausse", "BSD-3-Clause"),
+    ("BSC-3-Clause", "BSD-3-Clause"),
+    ("BSD2-Clause", "BSD-2-Clause"),
+    ("CC-BY-4", "CC-BY-4.0"),
+    ("CC-BY-SA-4", "CC-BY-SA-4.0"),
+    ("Expat", "MIT"),
+    ("GFDL-1.1", "GFDL-1.1-only"),
+    ("GFDL-1.2", "GFDL-1.2-only"),
+    ("GFDL-1.3", "GFDL-1.3-only"),
+    ("GPL-1.0", "GPL-1.0-only"),
+    ("GPL-2.0", "GPL-2.0-only"),
+    ("GPL-2.0+", "GPL-2.0-or-later"),
+    ("GPL-2.0-later", "GPL-2.0-or-later"),
+    ("GPL-2.0-or-laster", "GPL-2.0-or-later"),
+    ("GPL-2.0.or-later", "GPL-2.0-or-later"),
+    ("GPL2.0-or-later", "GPL-2.0-or-later"),
+    (
+        "GPL-2.0-with-classpath-exception",
+        "GPL-2.0-only WITH Classpath-exception-2.0",
+    ),
+    ("GPL-3.0", "GPL-3.0-only"),
+    ("GPL-3.0+", "GPL-3.0-or-later"),
+    ("GPL-3-or-later", "GPL-3.0-or-later"),
+    ("GPL-3.0-or-alter", "GPL-3.0-or-later"),
+    ("GPL-3.0-or-laer", "GPL-3.0-or-later"),
+    ("GPL-3.0-r-later", "GPL-3.0-or-later"),
+    (
+        "GPL-3.0-with-autoconf-exception",
+        "GPL-3.0-only WITH Autoconf-exception-3.0",
+    ),
+    ("GPLv2", "GPL-2.0-only"),
+    ("GPLv2+", "GPL-2.0-or-later"),
+    ("GPLv3", "GPL-3.0-only"),
+    ("GPLv3+", "GPL-3.0-or-later"),
+    ("LGPL-2.0", "LGPL-2.0-only"),
+    ("LGPL-2.0-later", "LGPL-2.0-or-later"),
+    ("LGPL-2.0-r-later", "LGPL-2.0-or-later"),
+    ("LGPL-2.1", "LGPL-2.1-only"),
+    ("LGPL-2.1+", "LGPL-2.1-or-later"),
+    ("LGPL-3.0", "LGPL-3.0-only"),
+    ("LGPL-3.0.only", "LGPL-3.0-only"),
+    ("LGPLv2", "LGPL-2.0-only"),
+    ("LGPLv2+", "LGPL-2.0-or-later"),
+    ("LGPLv2.1", "LGPL-2.1-only"),
+    ("LGPLv2.1+", "LGPL-2.1-or-later"),
+    ("LGPLv3", "LGPL-3.0-only"),
+    ("LGPLv3+", "LGPL-3.0-or-later"),
+    ("LPGL-2.0-only", "LGPL-2.0-only"),
+    ("LPGL-2.0-or-later", "LGPL-2.0-or-later"),
+    ("LPGL-3.0-or-later", "LGPL-3.0-or-later"),
+    ("MPL-1", "MPL-1.0"),
+    ("Public Domain", "LicenseRef-Public-Domain"),
+    ("Public-Domain", "LicenseRef-Public-Domain"),
+    ("SIL-1.1", "OFL-1.1"),
+];
+
+/// Table mapping eopkg license strings to SPDX expressions
+#[derive(Debug, Clone)]
+pub struct Licenses {
+    /// Keyed by the lowercased license string
+    table: BTreeMap<String, String>,
+}
+
+/// Licenses of a package once normalized
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Normalized {
+    /// SPDX expressions, in the order they were declared
+    pub licenses: Vec<String>,
+    /// Strings that could not be mapped, carried over verbatim
+    pub unknown: Vec<String>,
+}
+
+impl Default for Licenses {
+    fn default() -> Self {
+        let table = SPDX
+            .iter()
+            .map(|id| (id.to_lowercase(), id.to_string()))
+            .chain(
+                ALIASES
+                    .iter()
+                    .map(|(alias, id)| (alias.to_lowercase(), id.to_string())),
+            )
+            .collect();
+        Self { table }
+    }
+}
+
+impl Licenses {
+    /// The built-in table extended with `mapping`, which takes precedence
+    pub fn with(mapping: &BTreeMap<String, String>) -> Self {
+        let mut licenses = Self::default();
+        licenses.table.extend(
+            mapping
+                .iter()
+                .map(|(from, to)| (from.to_lowercase(), to.clone())),
+        );
+        licenses
+    }
+
+    /// SPDX expression for a single identifier or `WITH` expression
+    fn lookup(&self, license: &str) -> Option<String> {
+        if let Some(id) = self.table.get(&license.to_lowercase()) {
+            return Some(id.clone());
+        }
+        let (license, exception) = license.split_once(" WITH ")?;
+        let id = self.table.get(&license.trim().to_lowercase())?;
+        Some(format!("{id} WITH {}", exception.trim()))
+    }
+
+    /// Normalize the declared licenses of a package. Some entries hold a
+    /// YAML style list with comments, which are split into their parts.
+    pub fn normalize<'a>(&self, licenses: impl IntoIterator<Item = &'a str>) -> Normalized {
+        let mut normalized = Normalized::default();
+        let parts = licenses.into_iter().flat_map(|l| {
+            l.lines().filter_map(|line| {
+                let line = line.trim().trim_start_matches("- ");
+                let line = line.split_once(" #").map_or(line, |(l, _)| l).trim();
+                (!line.is_empty()).then_some(line)
+            })
+        });
+        for part in parts {
+            match self.lookup(part) {
+                Some(id) => normalized.licenses.push(id),
+                None => {
+                    normalized.unknown.push(part.to_string());
+                    normalized.licenses.push(part.to_string());
+                }
+            }
+        }
+        normalized
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::Licenses;
+
+    #[test]
+    fn normalize() {
+        let licenses = Licenses::default();
+        let normalized = licenses.normalize([
+            "GPLv2+",
+            "bsd-2-clause",
+            "LGPL-2.0-or-later WITH WxWindows-exception-3.1",
+            "- CC-BY-SA-3.0 #Cataclysm-DDA\n- Zlib #PLF List/Colony\n",
+            "Distributable",
+        ]);
+        assert_eq!(
+            normalized.licenses,
+            [
+                "GPL-2.0-or-later",
+                "BSD-2-Clause",
+                "LGPL-2.0-or-later WITH WxWindows-exception-3.1",
+                "CC-BY-SA-3.0",
+                "Zlib",
+                "Distributable",
+            ]
+        );
+        assert_eq!(normalized.unknown, ["Distributable"]);
+
+        let mapping = BTreeMap::from([("Distributable".into(), "LicenseRef-Distributable".into())]);
+        let normalized = Licenses::with(&mapping).normalize(["Distributable"]);
+        assert_eq!(normalized.licenses, ["LicenseRef-Distributable"]);
+        assert!(normalized.unknown.is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Aggregate bandwidth limiting shared by concurrent downloads
+
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Instant},
+};
+
+/// Token bucket capping the combined transfer rate of all holders
+pub struct RateLimit {
+    /// Permitted bytes per second
+    rate: f64,
+
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Bytes that may still be transferred, negative when in debt
+    available: f64,
+
+    /// Last time the bucket was refilled
+    refilled: Instant,
+}
+
+impl RateLimit {
+    /// Create a new limit of `rate` bytes per second
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            state: Mutex::new(State {
+                available: rate as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Account for `bytes` having been transferred, sleeping for as long
+    /// as needed to stay within the limit
+    pub async fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().await;
+            let now = Instant::now();
+            let refill = now.duration_since(state.refilled).as_secs_f64() * self.rate;
+            state.available = (state.available + refill).min(self.rate);
+            state.refilled = now;
+            state.available -= bytes as f64;
+
+            if state.available < 0.0 {
+                Duration::from_secs_f64(-state.available / self.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// Parse a size such as `500K` or `5M` into bytes, as given for rates and
+/// thresholds
+pub fn parse_size(input: &str) -> Result<u64, Error> {
+    let input = input.trim();
+    let (digits, multiplier) = match input.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&input[..i], 1024),
+        Some((i, 'm' | 'M')) => (&input[..i], 1024 * 1024),
+        Some((i, 'g' | 'G')) => (&input[..i], 1024 * 1024 * 1024),
+        _ => (input, 1),
+    };
+
+    let size = digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|d| d.checked_mul(multiplier))
+        .ok_or_else(|| Error::InvalidSize(input.to_string()))?;
+    if size == 0 {
+        return Err(Error::InvalidSize(input.to_string()));
+    }
+
+    Ok(size)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid size: {0}")]
+    InvalidSize(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_size;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("100K").unwrap(), 100 * 1024);
+        assert_eq!(parse_size("5M").unwrap(), 5 * 1024 * 1024);
+        assert_eq!(parse_size("1g").unwrap(), 1024 * 1024 * 1024);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("fast").is_err());
+        assert!(parse_size("M").is_err());
+    }
+}
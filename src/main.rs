@@ -3,30 +3,46 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::{
-    collections::{BTreeMap, HashMap},
-    fs::{create_dir, remove_dir_all, File},
-    io::{Cursor, Write},
+    collections::{BTreeMap, BTreeSet},
+    fs::{create_dir, create_dir_all, remove_dir_all, File},
+    future::Future,
+    io::{BufReader, BufWriter, Write},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
 use a_piece_of_pisi::{
-    converter::{convert, HashedPackage},
+    cache::{self, IndexCache},
+    config::{self, Config, Credential},
+    converter::{self, convert, HashedPackage, IndexSnapshot, Manifest, Payload, Provenance},
     eopkg::{
         self,
-        index::{Index, Package},
+        index::{Compression, Index, Package, Precedence},
+        name::PackageName,
+        package::Archive,
+        pspec::Pspec,
     },
+    fetch::{self, Downloader, Fetcher, HttpFetcher},
+    graph::{self, Graph, Level, Metadata},
+    http::{self, Client},
+    license::Licenses,
+    limit::{self, RateLimit},
+    mirror::{self, Scheduler},
+    recipe::Recipe,
+    schema,
 };
+use clap::{parser::ValueSource, value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
 use crossterm::style::Stylize;
-use dag::Dag;
-use indicatif::{style::TemplateError, MultiProgress, ProgressBar, ProgressStyle};
-use lzma::LzmaReader;
+use indicatif::{style::TemplateError, HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
 use reqwest::Url;
-use serde_xml_rs::from_reader;
 
 use futures::{stream, StreamExt, TryStreamExt};
-use sha2::{Digest, Sha256};
 use thiserror::Error;
+use tokio::sync::watch;
 use url::ParseError;
 
 use color_eyre::Result;
@@ -34,6 +50,39 @@ use color_eyre::Result;
 /// Limit concurrency to 8 jobs
 const CONCURRENCY_LIMIT: usize = 8;
 
+/// Components converted unless others are selected
+const DEFAULT_COMPONENTS: [&str; 2] = ["system.base", "system.devel"];
+
+/// Packages converted along with the selected components
+const EXTENSIONS: [&str; 8] = [
+    "libgcrypt",
+    "libgnutls",
+    "lsb-release",
+    "inxi",
+    "file",
+    "tree",
+    "which",
+    "man-db",
+];
+
+/// Default number of concurrent connections to a single mirror host
+const CONNECTIONS_PER_HOST: &str = "4";
+
+/// Set while previewing a recipe with `--show`, which keeps stdout for the
+/// recipe alone
+static PREVIEW: AtomicBool = AtomicBool::new(false);
+
+/// Print a status line, to stderr while previewing a recipe
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if PREVIEW.load(Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("uri parse: {0}")]
@@ -42,36 +91,159 @@ pub enum Error {
     #[error("reqwest: {0}")]
     Reqwest(#[from] reqwest::Error),
 
+    #[error("http: {0}")]
+    Http(#[from] http::Error),
+
+    #[error("config: {0}")]
+    Config(#[from] config::Error),
+
+    #[error("mirror: {0}")]
+    Mirror(#[from] mirror::Error),
+
+    #[error("cache: {0}")]
+    Cache(#[from] cache::Error),
+
+    #[error("index: {0}")]
+    Index(#[from] eopkg::index::Error),
+
+    #[error("xml: {0}")]
+    Xml(#[from] quick_xml::DeError),
+
+    #[error("fetch: {0}")]
+    Fetch(#[from] fetch::Error),
+
+    #[error("converter: {0}")]
+    Converter(#[from] converter::Error),
+
+    #[error("pspec: {0}")]
+    Pspec(#[from] eopkg::pspec::Error),
+
+    #[error("archive: {0}")]
+    Archive(#[from] eopkg::package::Error),
+
+    #[error("graph: {0}")]
+    Graph(#[from] graph::Error),
+
     #[error("io: {0}")]
     IO(#[from] std::io::Error),
 
-    #[error("invalid uri")]
-    InvalidURI,
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
 
     #[error("invalid template: {0}")]
     Template(#[from] TemplateError),
 
-    #[error("unknown package")]
-    UnknownPackage,
+    #[error("unknown package: {0}")]
+    UnknownPackage(String),
+
+    #[error("recipe of {0} does not match the stone.yml schema")]
+    InvalidRecipe(String),
+
+    #[error("converted recipe {path}: {source}")]
+    ConvertedRecipe {
+        path: String,
+        source: serde_yaml::Error,
+    },
+
+    #[error("no reachable mirror")]
+    NoMirror,
+
+    #[error("task: {0}")]
+    Task(#[from] tokio::task::JoinError),
+
+    #[error("interrupted")]
+    Interrupted,
+}
+
+/// Ctrl-C, as seen by each phase of a run. Blocking phases run on their
+/// own threads, which are abandoned rather than waited for, and a second
+/// Ctrl-C exits right away.
+#[derive(Clone)]
+struct Interrupt(watch::Receiver<bool>);
+
+impl Interrupt {
+    /// Catch Ctrl-C for the rest of the run
+    fn listen() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                let _ = sender.send(true);
+            }
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        });
+        Self(receiver)
+    }
+
+    /// Stop if Ctrl-C was pressed, reporting how far the run got
+    fn check(&self, multi: &MultiProgress, progress: impl FnOnce() -> String) -> Result<(), Error> {
+        if *self.0.borrow() {
+            return Err(Self::stop(multi, progress));
+        }
+        Ok(())
+    }
+
+    /// Clear the progress bars and report how far the run got
+    fn stop(multi: &MultiProgress, progress: impl FnOnce() -> String) -> Error {
+        if let Err(e) = multi.clear() {
+            return e.into();
+        }
+        status!("{} after {}", "Interrupted".red(), progress());
+        Error::Interrupted
+    }
+
+    /// Run `future` unless Ctrl-C is pressed first, which drops it and
+    /// removes the partial downloads it leaves in `cache_dir`
+    async fn guard<T, E>(
+        &self,
+        future: impl Future<Output = Result<T, E>>,
+        cache_dir: &Path,
+        multi: &MultiProgress,
+        progress: impl FnOnce() -> String,
+    ) -> Result<T, Error>
+    where
+        Error: From<E>,
+    {
+        let mut receiver = self.0.clone();
+        tokio::select! {
+            result = future => Ok(result?),
+            _ = receiver.wait_for(|pressed| *pressed) => {
+                fetch::remove_partial(cache_dir)?;
+                Err(Self::stop(multi, progress))
+            }
+        }
+    }
+
+    /// Run the blocking `work` on its own thread unless Ctrl-C is pressed
+    /// first, which stops waiting for it
+    async fn guard_blocking<T: Send + 'static>(
+        &self,
+        work: impl FnOnce() -> Result<T, Error> + Send + 'static,
+        cache_dir: &Path,
+        multi: &MultiProgress,
+        progress: impl FnOnce() -> String,
+    ) -> Result<T, Error> {
+        let task = tokio::task::spawn_blocking(work);
+        self.guard(async { task.await? }, cache_dir, multi, progress)
+            .await
+    }
 }
 
 /// Asynchronously fetch a package
-/// TODO: Filter already fetched!
-async fn fetch(
+async fn fetch<F: Fetcher>(
+    downloader: &Downloader<'_, F>,
     multi: &MultiProgress,
     total: &ProgressBar,
     p: &Package,
-    origin: &Url,
-    cache_dir: &Path,
 ) -> Result<HashedPackage, Error> {
-    let uri = origin.join(&p.package_uri)?;
-    let path = uri
-        .path_segments()
-        .ok_or(Error::InvalidURI)?
-        .last()
-        .ok_or(Error::InvalidURI)?
-        .to_string();
-    let mut r = reqwest::get(uri).await?;
+    let name = fetch::file_name(p)?.to_string();
+    if let Some(hashed) = downloader.cached(p).await? {
+        multi.println(format!("{} {}", "Cached".cyan(), name.bold()))?;
+        total.inc(1);
+        return Ok(hashed);
+    }
+
     let pbar = multi.insert_before(total, ProgressBar::new(p.package_size));
     pbar.set_style(
         ProgressStyle::with_template(
@@ -79,35 +251,30 @@ async fn fetch(
         )?
         .progress_chars("##-"),
     );
-    pbar.set_message(path.clone());
+    pbar.set_message(name.clone());
     pbar.enable_steady_tick(Duration::from_millis(150));
 
-    let mut hasher = Sha256::new();
-    let output_path = cache_dir.join(&path);
-    let mut output = File::create(&output_path).unwrap();
-
-    while let Some(chunk) = &r.chunk().await? {
-        let mut cursor = Cursor::new(chunk);
-        let len = chunk.len();
-        std::io::copy(&mut cursor, &mut output)?;
-        pbar.inc(len as u64);
-        hasher.update(chunk);
-    }
-    let hash = hasher.finalize();
+    let hashed = match downloader.download(p, &pbar).await {
+        Ok(hashed) => hashed,
+        Err(e) => {
+            pbar.finish_and_clear();
+            return Err(e.into());
+        }
+    };
 
-    pbar.println(format!("{} {}", "Fetched".green(), path.clone().bold()));
+    pbar.println(format!("{} {}", "Fetched".green(), name.bold()));
     total.inc(1);
 
-    Ok(HashedPackage {
-        package: p.clone(),
-        hash: hash.into(),
-    })
+    Ok(hashed)
 }
 
-async fn parse_index() -> Result<Index, Error> {
-    let bytes = include_bytes!("../test/eopkg-index.xml.xz");
-    let cursor = Cursor::new(bytes);
-    let xml_bar = ProgressBar::new(bytes.len() as u64);
+/// Decompress and parse a local copy of the index
+///
+/// In `lenient` mode malformed packages are reported and left out, which
+/// returns `false` alongside the index
+fn parse_index(path: &Path, lenient: bool) -> Result<(Index, bool), Error> {
+    let file = File::open(path)?;
+    let xml_bar = ProgressBar::new(file.metadata()?.len());
     xml_bar.set_style(
         ProgressStyle::with_template(
             "[{elapsed_precise}]  {bar:20.red/white}  {bytes:>7}/{total_bytes:7} {wide_msg:>.dim}",
@@ -117,8 +284,16 @@ async fn parse_index() -> Result<Index, Error> {
     xml_bar.enable_steady_tick(Duration::from_millis(150));
     xml_bar.set_message("Loading eopkg-index.xml.xz");
 
-    let reader = LzmaReader::new_decompressor(xml_bar.wrap_read(cursor)).unwrap();
-    let doc: eopkg::index::Index = from_reader(reader).unwrap();
+    let reader = eopkg::index::decompress(BufReader::new(xml_bar.wrap_read(file)))?;
+    let (doc, complete) = if lenient {
+        let (doc, skipped) = eopkg::index::parse_lenient(reader)?;
+        for skip in skipped.iter() {
+            xml_bar.println(format!("{} {skip}", "Skipped".yellow()));
+        }
+        (doc, skipped.is_empty())
+    } else {
+        (eopkg::index::parse(reader)?, true)
+    };
     xml_bar.println(format!(
         "{} {}",
         "Loaded".blue(),
@@ -126,121 +301,1621 @@ async fn parse_index() -> Result<Index, Error> {
     ));
     xml_bar.finish_and_clear();
 
-    Ok(doc)
+    Ok((doc, complete))
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    color_eyre::install()?;
+/// Load the index at `uri`, reusing the cached snapshot when unchanged
+async fn load_index(
+    client: &Client,
+    cache: &IndexCache,
+    uri: &Url,
+    lenient: bool,
+) -> Result<(Index, IndexSnapshot), Error> {
+    let refreshed = cache.refresh(client, uri).await?;
+    let snapshot = IndexSnapshot {
+        uri: uri.to_string(),
+        sha256: refreshed.sha256()?,
+    };
 
-    let multi = MultiProgress::new();
-    let index = parse_index().await?;
-    let origin = Url::parse("https://packages.getsol.us/unstable/")?;
-    let cache_dir = PathBuf::from("cache");
-    if !cache_dir.exists() {
-        create_dir(&cache_dir)?;
-    }
+    // Loading and parsing block, so they run on their own thread for Ctrl-C
+    // to stop waiting on
+    let (cache, uri) = (cache.clone(), uri.clone());
+    let loading = tokio::task::spawn_blocking(move || -> Result<Index, Error> {
+        if !refreshed.changed {
+            if let Some(index) = cache.snapshot(&uri) {
+                status!("{} {}", "Unchanged".blue(), uri.as_str().bold());
+                return Ok(index);
+            }
+        }
 
-    let mapping: BTreeMap<_, _> = index.packages.iter().map(|p| (p.name.clone(), p)).collect();
+        // Only a complete parse is worth reusing, to report skipped packages again
+        let (index, complete) = parse_index(&refreshed.path, lenient)?;
+        if complete {
+            cache.store_snapshot(&uri, &index)?;
+        }
+        Ok(index)
+    });
+    Ok((loading.await??, snapshot))
+}
 
-    let mut base = mapping
-        .values()
-        .filter_map(|m| {
-            if let Some(component) = &m.part_of {
-                if component == "system.base" || component == "system.devel" {
-                    Some(m.name.clone())
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        })
+fn command() -> Command {
+    Command::new(env!("CARGO_PKG_NAME"))
+        .about("Convert eopkg packages into stone recipes")
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("YAML configuration file")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("index")
+                .long("index")
+                .value_name("URI")
+                .help("Index to convert from, may be repeated to layer repositories. Defaults to the index of the first mirror")
+                .value_parser(value_parser!(Url))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("precedence")
+                .long("precedence")
+                .value_name("first|last|newest")
+                .help("Which index wins when several provide the same package")
+                .default_value("first"),
+        )
+        .arg(
+            Arg::new("component")
+                .long("component")
+                .value_name("NAME")
+                .help("Component to convert, such as system.base or system.*, may be repeated. Defaults to the selection of the config, if it has one")
+                .action(ArgAction::Append)
+                .default_values(DEFAULT_COMPONENTS),
+        )
+        .arg(
+            Arg::new("keep-dbginfo")
+                .long("keep-dbginfo")
+                .help("Resolve and convert -dbginfo packages instead of leaving them out")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("lenient")
+                .long("lenient")
+                .help("Skip malformed packages in the index instead of failing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("mirror")
+                .long("mirror")
+                .value_name("URI")
+                .help("Mirror to fetch packages from, may be repeated")
+                .action(ArgAction::Append)
+                .default_value("https://packages.getsol.us/unstable/")
+                .value_parser(value_parser!(Url)),
+        )
+        .arg(
+            Arg::new("rank-mirrors")
+                .long("rank-mirrors")
+                .help("Probe all mirrors first and prefer the fastest")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Only list the packages that would be converted and their sizes")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("write-index")
+                .long("write-index")
+                .value_name("FILE")
+                .help("Write an index of just the converted packages, compressed by extension")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("max-depth")
+                .long("max-depth")
+                .value_name("DEPTH")
+                .help("Only follow dependencies this many levels deep from the selected packages, 1 for their direct dependencies")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("unknown-leaves")
+                .long("unknown-leaves")
+                .help("Keep dependencies missing from the index in the graph as leaves, rather than leaving them out")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("converted")
+                .long("converted")
+                .value_name("PATH")
+                .help("Recipes directory, or file listing one source per line, already converted. Resolution stops at their packages. May be repeated")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("pspec-dir")
+                .long("pspec-dir")
+                .value_name("DIR")
+                .help("Checkout of the source repository, flat or with a packages/<letter>/ tree, to recover build dependencies")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_name("DIR")
+                .help("Directory the recipes are written to")
+                .default_value("binary-conversion")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("layout")
+                .long("layout")
+                .value_name("flat|recipes")
+                .help("Write <source>/stone.yml, or <initial>/<source>/stone.yml as in a recipes checkout")
+                .default_value("flat"),
+        )
+        .arg(
+            Arg::new("show")
+                .long("show")
+                .value_name("SOURCE")
+                .help("Convert only the recipe of SOURCE, printing it rather than writing files"),
+        )
+        .arg(
+            Arg::new("strict-schema")
+                .long("strict-schema")
+                .help("Fail instead of warning when a recipe would be rejected by boulder")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("merge")
+                .long("merge")
+                .help("Only refresh the version, release and upstreams of existing recipes, keeping manual edits")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("source")
+                .long("source")
+                .help("Build from the upstream archives listed in each pspec, rather than repackaging binaries")
+                .requires("pspec-dir")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("language")
+                .long("language")
+                .value_name("LANG")
+                .help("Preferred language of summaries and descriptions")
+                .default_value(eopkg::index::DEFAULT_LANGUAGE),
+        )
+        .arg(
+            Arg::new("changelog")
+                .long("changelog")
+                .help("Append the package history to each recipe as comments")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("checksums")
+                .long("checksums")
+                .help("Append the sha1 and sha256 of each eopkg to the recipe as comments")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("bucketing")
+                .long("bucketing")
+                .value_name("source|package")
+                .help("Generate one recipe per source, or one per binary package"),
+        )
+        .arg(
+            Arg::new("mismatch")
+                .long("mismatch")
+                .value_name("warn|highest|fail")
+                .help("When packages of a recipe are at different releases, warn, use the highest, or fail"),
+        )
+        .arg(
+            Arg::new("release")
+                .long("release")
+                .value_name("keep|reset|OFFSET")
+                .help("Carry over the eopkg release, restart it at 1, or shift it by OFFSET")
+                .default_value("keep")
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::new("repack-uri")
+                .long("repack-uri")
+                .value_name("URI")
+                .help("Repack payloads into cache/repacked as plain tarballs, to be published at URI")
+                .value_parser(value_parser!(Url)),
+        )
+        .arg(
+            Arg::new("usr-merge")
+                .long("usr-merge")
+                .help("Relocate payloads installing into /bin, /sbin or /lib into /usr")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .value_name("FILE")
+                .help("Tera template rendering each stone.yml from the converted recipe")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("connections-per-host")
+                .long("connections-per-host")
+                .value_name("COUNT")
+                .help("Maximum concurrent downloads from a single mirror host")
+                .default_value(CONNECTIONS_PER_HOST)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("limit-rate")
+                .long("limit-rate")
+                .value_name("RATE")
+                .help("Cap the combined download rate, e.g. 500K or 5M")
+                .value_parser(limit::parse_size),
+        )
+        .arg(
+            Arg::new("segment-threshold")
+                .long("segment-threshold")
+                .value_name("SIZE")
+                .help("Fetch archives of at least this size over several connections")
+                .default_value("64M")
+                .value_parser(limit::parse_size),
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .value_name("URI")
+                .help("Proxy for all requests, overriding HTTP_PROXY/HTTPS_PROXY")
+                .value_parser(value_parser!(Url)),
+        )
+        .arg(
+            Arg::new("connect-timeout")
+                .long("connect-timeout")
+                .value_name("SECONDS")
+                .help("Give up connecting to a server after this long")
+                .default_value("30")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("read-timeout")
+                .long("read-timeout")
+                .value_name("SECONDS")
+                .help("Give up on a transfer that stalls for this long")
+                .default_value("60")
+                .value_parser(value_parser!(u64)),
+        )
+        .subcommand(
+            Command::new("extract")
+                .about("Extract the payload of a package into a directory")
+                .arg(
+                    Arg::new("package")
+                        .value_name("PACKAGE")
+                        .help("Package name from the index, or path to an .eopkg")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("directory")
+                        .value_name("DIR")
+                        .help("Directory to extract into")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("graph")
+                .about("Write the dependency graph of the selected packages")
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .help("File to write the graph to")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("dot|json|mermaid")
+                        .help("Format of the graph")
+                        .default_value("dot"),
+                )
+                .arg(
+                    Arg::new("level")
+                        .long("level")
+                        .value_name("package|source")
+                        .help("Whether nodes are binary packages, or the recipes building them")
+                        .default_value("package"),
+                ),
+        )
+        .subcommand(
+            Command::new("rdepends")
+                .about("List the packages depending on a package, directly and transitively")
+                .arg(
+                    Arg::new("package")
+                        .value_name("PACKAGE")
+                        .help("Package name from the index")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("selection")
+                        .long("selection")
+                        .help("Only consider the selected packages and their dependencies, not the whole index")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare the dependency closure of the selection with that of another")
+                .arg(
+                    Arg::new("component")
+                        .long("component")
+                        .value_name("NAME")
+                        .help("Component of the other selection, may be repeated")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Configuration file with the other selection")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .group(
+                    ArgGroup::new("other")
+                        .args(["component", "config"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("why")
+                .about("Show the shortest dependency path from the selected packages to a package")
+                .arg(
+                    Arg::new("package")
+                        .value_name("PACKAGE")
+                        .help("Package name from the index")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("weigh")
+                .about("Report the heaviest dependency chains and the exclusions saving the most download volume")
+                .arg(
+                    Arg::new("top")
+                        .long("top")
+                        .value_name("COUNT")
+                        .help("Number of chains and exclusions to report")
+                        .default_value("10")
+                        .value_parser(value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            Command::new("convert-file")
+                .about("Convert a local .eopkg from its embedded metadata, without an index")
+                .arg(
+                    Arg::new("path")
+                        .value_name("PATH")
+                        .help("Path to the .eopkg")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("uri")
+                        .long("uri")
+                        .value_name("URI")
+                        .help("Directory the archive is published in, defaults to its local directory")
+                        .value_parser(value_parser!(Url)),
+                ),
+        )
+}
+
+/// Packages of the components matching `patterns`, along with the
+/// extensions
+fn select<'a>(
+    index: &Index,
+    patterns: impl IntoIterator<Item = &'a str>,
+    keep_dbginfo: bool,
+) -> Vec<PackageName> {
+    let mut selected = patterns
+        .into_iter()
+        .flat_map(|pattern| index.packages_in(pattern))
+        .filter(|p| keep_dbginfo || !p.name.is_dbginfo())
+        .map(|p| p.name.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
         .collect::<Vec<_>>();
+    selected.extend(EXTENSIONS.into_iter().map(PackageName::from));
+    selected
+}
+
+/// How far resolution follows dependencies
+struct Limits<'a> {
+    /// Recipes converted before, whose dependencies are not followed
+    converted: &'a BTreeSet<String>,
+    max_depth: Option<usize>,
+    keep_dbginfo: bool,
+    /// Keep dependencies missing from the index as leaves of the graph
+    unknown_leaves: bool,
+}
+
+/// Dependencies of a selection, once resolved
+struct Resolution {
+    graph: Graph<PackageName>,
+    /// Packages of converted recipes
+    pruned: BTreeSet<PackageName>,
+    /// Dependencies missing from the index, and the packages wanting them
+    unknown: BTreeMap<PackageName, Vec<PackageName>>,
+}
 
-    let extensions = [
-        "libgcrypt",
-        "libgnutls",
-        "lsb-release",
-        "inxi",
-        "file",
-        "tree",
-        "which",
-        "man-db",
-    ];
-    base.extend(extensions.iter().map(|m| m.to_string()));
-
-    let mut graph: Dag<String> = Dag::new();
-
-    // Solve ...
-    let mut processing = base.clone();
+/// Resolve the run dependencies of `roots` level by level, within `limits`.
+/// Roots must be in the index, dependencies missing from it are collected.
+fn resolve(
+    roots: &[PackageName],
+    mapping: &BTreeMap<PackageName, &Package>,
+    limits: &Limits,
+    options: &converter::Options,
+) -> Result<Resolution, Error> {
+    if let Some(root) = roots.iter().find(|r| !mapping.contains_key(*r)) {
+        return Err(Error::UnknownPackage(root.to_string()));
+    }
+
+    let mut graph = Graph::default();
+    let mut pruned = BTreeSet::new();
+    let mut unknown = BTreeSet::new();
+
+    let mut processing = roots.to_vec();
+    let mut depth = 0;
     while !&processing.is_empty() {
+        let expand = limits.max_depth.is_none_or(|max| depth < max);
         let mut next = vec![];
         for pkg in processing.iter() {
-            let pkg = mapping.get(pkg).ok_or(Error::UnknownPackage)?;
-            let our_index = graph.add_node_or_get_index(pkg.name.clone());
-            if let Some(deps) = &pkg.run_deps {
+            // Virtual and stale dependencies are reported once resolved
+            let Some(pkg) = mapping.get(pkg) else {
+                unknown.insert(pkg.clone());
+                continue;
+            };
+            graph.add_node(pkg.name.clone());
+            // Converted before, along with what it depends on
+            let recipe = options.rename(options.bucketing.key(pkg));
+            if limits.converted.contains(&recipe) {
+                pruned.insert(pkg.name.clone());
+                continue;
+            }
+            if let Some(deps) = pkg.run_deps.as_ref().filter(|_| expand) {
                 for dep in &deps.deps {
-                    let child_index = if let Some(child_index) = graph.get_index(&dep.value) {
-                        // Already exists..
-                        child_index
-                    } else {
-                        // Create the child index.
+                    if !limits.keep_dbginfo && dep.value.is_dbginfo() {
+                        continue;
+                    }
+                    if !graph.contains(&dep.value) {
                         next.push(dep.value.clone());
-                        graph.add_node_or_get_index(dep.value.clone())
-                    };
-                    graph.add_edge(our_index, child_index);
+                    }
+                    graph.add_edge(pkg.name.clone(), dep.value.clone());
                 }
             }
         }
         processing = next;
+        depth += 1;
+    }
+
+    let unknown = unknown
+        .into_iter()
+        .map(|name| {
+            let wanted_by = graph.dependents(&name).cloned().collect();
+            (name, wanted_by)
+        })
+        .collect::<BTreeMap<_, _>>();
+    if !limits.unknown_leaves {
+        for name in unknown.keys() {
+            graph.remove_node(name);
+        }
+    }
+    Ok(Resolution {
+        graph,
+        pruned,
+        unknown,
+    })
+}
+
+/// [`resolve`] as owned work for [`Interrupt::guard_blocking`]
+fn resolving(
+    index: Arc<Index>,
+    roots: Vec<PackageName>,
+    limits: &Limits,
+    options: converter::Options,
+) -> impl FnOnce() -> Result<Resolution, Error> + Send + 'static {
+    let converted = limits.converted.clone();
+    let Limits {
+        max_depth,
+        keep_dbginfo,
+        unknown_leaves,
+        ..
+    } = *limits;
+    move || {
+        let mapping = index.packages.iter().map(|p| (p.name.clone(), p)).collect();
+        let limits = Limits {
+            converted: &converted,
+            max_depth,
+            keep_dbginfo,
+            unknown_leaves,
+        };
+        resolve(&roots, &mapping, &limits, &options)
+    }
+}
+
+/// Names of the recipes already converted, listed one per line in the file
+/// at `path` or found as `stone.yml` files beneath the directory
+fn converted_recipes(path: &Path) -> Result<BTreeSet<String>, Error> {
+    if !path.is_dir() {
+        return Ok(std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(str::to_string)
+            .collect());
+    }
+
+    let mut names = BTreeSet::new();
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.file_name().is_some_and(|n| n == "stone.yml") {
+                let recipe = Recipe::parse(&std::fs::read_to_string(&path)?).map_err(|source| {
+                    Error::ConvertedRecipe {
+                        path: path.display().to_string(),
+                        source,
+                    }
+                })?;
+                names.insert(recipe.name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Options of the conversion, as given on the command line or in the config
+fn converter_options(matches: &ArgMatches, config: &Config) -> Result<converter::Options> {
+    Ok(converter::Options {
+        language: matches
+            .get_one::<String>("language")
+            .cloned()
+            .unwrap_or_default(),
+        changelog: matches.get_flag("changelog"),
+        licenses: Licenses::with(&config.licenses),
+        keep_dbginfo: matches.get_flag("keep-dbginfo"),
+        template: matches
+            .get_one::<PathBuf>("template")
+            .map(std::fs::read_to_string)
+            .transpose()?,
+        renames: config.renames.clone(),
+        components: config.components.clone(),
+        usr_merge: matches.get_flag("usr-merge"),
+        bucketing: match matches.get_one::<String>("bucketing") {
+            Some(bucketing) => bucketing.parse()?,
+            None => config.bucketing.unwrap_or_default(),
+        },
+        repack_uri: matches.get_one::<Url>("repack-uri").cloned(),
+        release: matches
+            .get_one::<String>("release")
+            .map(|r| r.parse())
+            .transpose()?
+            .unwrap_or_default(),
+        source: matches.get_flag("source"),
+        recipe: config.recipe.clone(),
+        overrides: config.overrides.clone(),
+        snapshot: None,
+        mismatch: match matches.get_one::<String>("mismatch") {
+            Some(mismatch) => mismatch.parse()?,
+            None => config.mismatch.unwrap_or_default(),
+        },
+        checksums: matches.get_flag("checksums"),
+    })
+}
+
+/// Write the files of one converted recipe into `tree`. Monitoring is left
+/// out when an existing one should be kept.
+fn write_recipe(
+    tree: &Path,
+    recipe: &str,
+    monitoring: Option<&str>,
+    provenance: &Provenance,
+) -> Result<(), Error> {
+    create_dir_all(tree)?;
+    File::create(tree.join("stone.yml"))?.write_all(recipe.as_bytes())?;
+    if let Some(monitoring) = monitoring {
+        File::create(tree.join("monitoring.yaml"))?.write_all(monitoring.as_bytes())?;
+    }
+
+    let mut file = BufWriter::new(File::create(tree.join("provenance.json"))?);
+    serde_json::to_writer_pretty(&mut file, provenance)?;
+    file.write_all(b"\n")?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Convert the archive at `path` on its own, trusting its embedded metadata
+fn convert_file(
+    path: &Path,
+    uri: Option<&Url>,
+    base_dir: &Path,
+    layout: converter::Layout,
+    options: &converter::Options,
+    strict_schema: bool,
+) -> Result<()> {
+    let mut archive = Archive::open(path)?;
+    let (hash, sha1) = fetch::hash_file(path)?;
+    let mut package = Package::from(archive.metadata()?);
+    package.package_uri = path
+        .file_name()
+        .ok_or_else(|| Error::UnknownPackage(path.display().to_string()))?
+        .to_string_lossy()
+        .to_string();
+    package.package_size = path.metadata()?.len();
+    package.package_hash = sha1;
+    let hashed = HashedPackage {
+        hash,
+        package,
+        files: Some(archive.files()?),
+        payload: None,
+    };
+
+    let base_uri = match uri {
+        Some(uri) => mirror::normalize(uri.clone()),
+        None => Url::from_directory_path(path.canonicalize()?.parent().expect("file"))
+            .map_err(|_| Error::URI(ParseError::RelativeUrlWithoutBase))?,
+    };
+    let source = options.bucketing.key(&hashed.package).to_string();
+    let converted = convert(vec![&hashed], base_uri, None, options)?;
+    for warning in converted.warnings.iter() {
+        status!("{} {warning}", "Warning".yellow());
+    }
+    let problems = schema::validate(&converted.recipe);
+    for problem in problems.iter() {
+        status!("{} {source}: {problem}", "Invalid".red());
+    }
+    if !problems.is_empty() && strict_schema {
+        return Err(Error::InvalidRecipe(source).into());
+    }
+
+    let tree = base_dir.join(layout.path(&options.rename(&source)));
+    let provenance = Provenance {
+        converter: env!("CARGO_PKG_VERSION").to_string(),
+        indexes: vec![],
+        packages: converted.origins,
+    };
+    write_recipe(
+        &tree,
+        &converted.recipe,
+        Some(&converted.monitoring),
+        &provenance,
+    )?;
+    status!(
+        "{} {} into {}",
+        "Converted".green(),
+        path.display().to_string().bold(),
+        tree.display()
+    );
+    Ok(())
+}
+
+/// Unpack the payload of the archive at `path` into `target`
+fn extract(path: &Path, target: &Path) -> Result<(), Error> {
+    if !target.exists() {
+        create_dir_all(target)?;
+    }
+    Archive::open(path)?.extract(target)?;
+    status!(
+        "{} {} into {}",
+        "Extracted".green(),
+        path.display().to_string().bold(),
+        target.display()
+    );
+    Ok(())
+}
+
+/// Probe the mirrors and return them ordered by speed
+async fn rank_mirrors(client: &Client, mirrors: &[Url]) -> Vec<Url> {
+    let (ranked, failed) = mirror::rank(client, mirrors).await;
+    for (mirror, e) in failed.iter() {
+        status!("{} {} ({e})", "Unreachable".red(), mirror.as_str().bold());
+    }
+    for benchmark in ranked.iter() {
+        let throughput = if benchmark.throughput.is_finite() {
+            format!("{}/s", HumanBytes(benchmark.throughput as u64))
+        } else {
+            "local".to_string()
+        };
+        status!(
+            "{} {} {}ms {throughput}",
+            "Probed".blue(),
+            benchmark.mirror.as_str().bold(),
+            benchmark.latency.as_millis(),
+        );
+    }
+
+    ranked.into_iter().map(|b| b.mirror).collect()
+}
+
+fn main() -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(run());
+    // Blocking work cut short by Ctrl-C is not waited for
+    runtime.shutdown_background();
+    result
+}
+
+async fn run() -> Result<()> {
+    color_eyre::install()?;
+
+    let matches = command().get_matches();
+    PREVIEW.store(matches.contains_id("show"), Ordering::Relaxed);
+
+    // Local archives can be extracted without consulting the index
+    let extract_args = matches.subcommand_matches("extract");
+    if let Some(args) = extract_args {
+        let package = args.get_one::<String>("package").expect("required");
+        let target = args.get_one::<PathBuf>("directory").expect("required");
+        if Path::new(package).is_file() {
+            extract(Path::new(package), target)?;
+            return Ok(());
+        }
+    }
+
+    let mut config = match matches.get_one::<PathBuf>("config") {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    config.credentials.extend(Credential::from_env());
+
+    let layout: converter::Layout = matches
+        .get_one::<String>("layout")
+        .expect("default")
+        .parse()?;
+    if let Some(args) = matches.subcommand_matches("convert-file") {
+        let path = args.get_one::<PathBuf>("path").expect("required");
+        let base_dir = matches.get_one::<PathBuf>("output").expect("default");
+        let options = converter_options(&matches, &config)?;
+        let strict_schema = matches.get_flag("strict-schema");
+        return convert_file(
+            path,
+            args.get_one::<Url>("uri"),
+            base_dir,
+            layout,
+            &options,
+            strict_schema,
+        );
+    }
+
+    // From here on a Ctrl-C stops the run between steps
+    let interrupt = Interrupt::listen();
+    let multi = MultiProgress::new();
+    let cache_dir = PathBuf::from("cache");
+    if !cache_dir.exists() {
+        create_dir(&cache_dir)?;
+    }
+
+    let client = Client::new(http::Options {
+        proxy: matches.get_one::<Url>("proxy").cloned(),
+        credentials: config.credentials.clone(),
+        connect_timeout: matches
+            .get_one::<u64>("connect-timeout")
+            .copied()
+            .map(Duration::from_secs),
+        read_timeout: matches
+            .get_one::<u64>("read-timeout")
+            .copied()
+            .map(Duration::from_secs),
+    })?;
+    let mut mirrors = matches
+        .get_many::<Url>("mirror")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .map(mirror::normalize)
+        .collect::<Vec<_>>();
+    if matches.get_flag("rank-mirrors") {
+        let ranking = async { Ok::<_, Error>(rank_mirrors(&client, &mirrors).await) };
+        mirrors = interrupt
+            .guard(ranking, &cache_dir, &multi, || "ranking mirrors".into())
+            .await?;
+    }
+    let origin = mirrors.first().cloned().ok_or(Error::NoMirror)?;
+    let limit = matches
+        .get_one::<u64>("limit-rate")
+        .copied()
+        .map(RateLimit::new);
+    let segment_threshold = *matches
+        .get_one::<u64>("segment-threshold")
+        .expect("default");
+    let scheduler = Scheduler::new(
+        &mirrors,
+        matches
+            .get_one::<usize>("connections-per-host")
+            .copied()
+            .unwrap_or_default(),
+    );
+
+    let index_uris = match matches.get_many::<Url>("index") {
+        Some(uris) => uris.cloned().collect(),
+        None => vec![origin.join("eopkg-index.xml.xz")?],
+    };
+    let precedence: Precedence = matches
+        .get_one::<String>("precedence")
+        .expect("default")
+        .parse()?;
+
+    let index_cache = IndexCache::new(&cache_dir);
+    let mut indexes = vec![];
+    let mut snapshots = vec![];
+    for (i, uri) in index_uris.iter().enumerate() {
+        let loading = load_index(&client, &index_cache, uri, matches.get_flag("lenient"));
+        let (mut index, snapshot) = interrupt
+            .guard(loading, &cache_dir, &multi, || {
+                format!("loading {i}/{} indexes", index_uris.len())
+            })
+            .await?;
+        snapshots.push(snapshot);
+        let distro = &index.distribution;
+        status!(
+            "{} {} {} ({}, version {}, {} packages)",
+            "Repository".blue(),
+            distro
+                .binary_name
+                .as_ref()
+                .unwrap_or(&distro.source_name)
+                .as_str()
+                .bold(),
+            distro.description.get(eopkg::index::DEFAULT_LANGUAGE),
+            distro.r#type,
+            distro.version,
+            index.packages.len()
+        );
+
+        // Packages of the first index come from the mirrors, the others
+        // from alongside their own index
+        if i > 0 {
+            let repository = uri.join(".")?;
+            for p in index.packages.iter_mut() {
+                p.repository = Some(repository.clone());
+            }
+        }
+        indexes.push(index);
+    }
+    let index = Arc::new(Index::merge(indexes, precedence).expect("at least one index"));
+
+    let mapping: BTreeMap<_, _> = index.packages.iter().map(|p| (p.name.clone(), p)).collect();
+    let fetcher = HttpFetcher::new(client.clone());
+
+    if let Some(args) = extract_args {
+        let package = args.get_one::<String>("package").expect("required");
+        let target = args.get_one::<PathBuf>("directory").expect("required");
+        let p = mapping
+            .get(package.as_str())
+            .ok_or_else(|| Error::UnknownPackage(package.clone()))?;
+        let downloader = Downloader {
+            fetcher: &fetcher,
+            scheduler: &scheduler,
+            cache_dir: &cache_dir,
+            limit: limit.as_ref(),
+            segment_threshold,
+            transferred: None,
+        };
+        let total = multi.add(ProgressBar::new(1));
+        let fetching = fetch(&downloader, &multi, &total, p);
+        interrupt
+            .guard(fetching, &cache_dir, &multi, || {
+                format!("fetching {package}")
+            })
+            .await?;
+        total.finish_and_clear();
+        extract(&downloader.cache_path(p)?, target)?;
+        return Ok(());
+    }
+
+    let options = converter::Options {
+        snapshot: index.last_updated(),
+        ..converter_options(&matches, &config)?
+    };
+    let bucketing = options.bucketing;
+
+    // Previews only need the packages of the one recipe
+    let show = matches.get_one::<String>("show");
+    let keep_dbginfo = options.keep_dbginfo;
+    let patterns = match matches.value_source("component") {
+        Some(ValueSource::DefaultValue) if !config.selection.is_empty() => config.selection.clone(),
+        _ => matches
+            .get_many::<String>("component")
+            .expect("default")
+            .cloned()
+            .collect(),
+    };
+    let mut base = select(&index, patterns.iter().map(String::as_str), keep_dbginfo);
+    if let Some(show) = show {
+        base = index
+            .packages
+            .iter()
+            .filter(|p| bucketing.key(p) == show.as_str())
+            .filter(|p| keep_dbginfo || !p.name.is_dbginfo())
+            .map(|p| p.name.clone())
+            .collect();
+        if base.is_empty() {
+            return Err(Error::UnknownPackage(show.clone()).into());
+        }
+    }
+
+    let converted = matches
+        .get_many::<PathBuf>("converted")
+        .into_iter()
+        .flatten()
+        .map(|path| converted_recipes(path))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect::<BTreeSet<_>>();
+
+    // Solve ... up to the depth asked for. A preview has the packages it
+    // needs already.
+    let limits = Limits {
+        converted: &converted,
+        max_depth: match show {
+            Some(_) => Some(0),
+            None => matches.get_one::<usize>("max-depth").copied(),
+        },
+        keep_dbginfo,
+        unknown_leaves: matches.get_flag("unknown-leaves"),
+    };
+    let Resolution {
+        graph,
+        pruned,
+        unknown,
+    } = interrupt
+        .guard_blocking(
+            resolving(index.clone(), base.clone(), &limits, options.clone()),
+            &cache_dir,
+            &multi,
+            || "resolving dependencies".into(),
+        )
+        .await?;
+
+    for (name, wanted_by) in unknown.iter() {
+        let wanted_by = wanted_by
+            .iter()
+            .map(PackageName::as_str)
+            .collect::<Vec<_>>();
+        status!(
+            "{} {} wanted by {}",
+            "Unknown".yellow(),
+            name.as_str().bold(),
+            wanted_by.join(", ")
+        );
+    }
+    if !pruned.is_empty() {
+        status!(
+            "{} {} packages of already converted recipes",
+            "Pruned".blue(),
+            pruned.len()
+        );
+    }
+
+    // Recipes are generated and built per source, so they are ordered by the
+    // dependencies between their packages
+    let sources = graph.map(|p| {
+        mapping
+            .get(p)
+            .filter(|_| !pruned.contains(p))
+            .map(|p| options.rename(bucketing.key(p)))
+    });
+
+    // The topological order breaks cycles arbitrarily, so name their members
+    for cycle in graph.cycles() {
+        status!(
+            "{} between {}",
+            "Cycle".yellow(),
+            cycle
+                .iter()
+                .map(PackageName::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+                .bold()
+        );
+    }
+
+    if let Some(args) = matches.subcommand_matches("graph") {
+        let path = args.get_one::<PathBuf>("file").expect("required");
+        let format: graph::Format = args.get_one::<String>("format").expect("default").parse()?;
+        let level: Level = args.get_one::<String>("level").expect("default").parse()?;
+        let exported = match (format, level) {
+            (graph::Format::Dot, Level::Package) => graph.to_dot("dependencies"),
+            (graph::Format::Dot, Level::Source) => sources.to_dot("dependencies"),
+            (graph::Format::Json, Level::Package) => {
+                graph.to_json(|p| mapping.get(p).map(|&p| Metadata::from(p)))?
+            }
+            (graph::Format::Json, Level::Source) => sources.to_json(|_| None::<Metadata>)?,
+            (graph::Format::Mermaid, Level::Package) => graph.to_mermaid(),
+            (graph::Format::Mermaid, Level::Source) => sources.to_mermaid(),
+        };
+        let exported_nodes = match level {
+            Level::Package => graph.nodes().count(),
+            Level::Source => sources.nodes().count(),
+        };
+        File::create(path)?.write_all(exported.as_bytes())?;
+        status!(
+            "{} {} with {} nodes",
+            "Wrote".blue(),
+            path.display().to_string().bold(),
+            exported_nodes
+        );
+        return Ok(());
+    }
+
+    if let Some(args) = matches.subcommand_matches("diff") {
+        // Another config brings its own renames and bucketing, which decide
+        // what counts as already converted
+        let (patterns, other_options) = match args.get_many::<String>("component") {
+            Some(patterns) => (patterns.cloned().collect(), None),
+            None => {
+                let path = args.get_one::<PathBuf>("config").expect("group");
+                let other = Config::load(path)?;
+                let other_options = converter_options(&matches, &other)?;
+                let patterns = if other.selection.is_empty() {
+                    DEFAULT_COMPONENTS.map(String::from).to_vec()
+                } else {
+                    other.selection
+                };
+                (patterns, Some(other_options))
+            }
+        };
+        let other_options = other_options.as_ref().unwrap_or(&options);
+        let other = select(&index, patterns.iter().map(String::as_str), keep_dbginfo);
+        let other = interrupt
+            .guard_blocking(
+                resolving(index.clone(), other, &limits, other_options.clone()),
+                &cache_dir,
+                &multi,
+                || "resolving the other selection".into(),
+            )
+            .await?
+            .graph;
+        let ours = graph.nodes().collect::<BTreeSet<_>>();
+        let theirs = other.nodes().collect::<BTreeSet<_>>();
+        for package in theirs.difference(&ours) {
+            status!("{} {}", "Enters".green(), package.as_str().bold());
+        }
+        for package in ours.difference(&theirs) {
+            status!("{} {}", "Leaves".red(), package.as_str().bold());
+        }
+        status!(
+            "{} {} packages against {}: {} enter, {} leave",
+            "Compared".blue(),
+            ours.len(),
+            theirs.len(),
+            theirs.difference(&ours).count(),
+            ours.difference(&theirs).count()
+        );
+        return Ok(());
+    }
+
+    if let Some(args) = matches.subcommand_matches("why") {
+        let package = PackageName::from(
+            args.get_one::<String>("package")
+                .expect("required")
+                .as_str(),
+        );
+        match graph.shortest_path(&base, &package) {
+            Some(path) => status!(
+                "{} {}",
+                "Pulled".blue(),
+                path.iter()
+                    .map(PackageName::as_str)
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+            None => status!(
+                "{} {} is not among the selected packages or their dependencies",
+                "Unused".yellow(),
+                package.as_str().bold()
+            ),
+        }
+        return Ok(());
+    }
+
+    if let Some(args) = matches.subcommand_matches("weigh") {
+        let top = args.get_one::<usize>("top").copied().unwrap_or_default();
+        let size = |p: &PackageName| match mapping.get(p) {
+            Some(p) if !pruned.contains(&p.name) => p.package_size,
+            _ => 0,
+        };
+        for (weight, chain) in graph.heaviest_chains(&base, size).into_iter().take(top) {
+            status!(
+                "{} {} {}",
+                "Chain".blue(),
+                HumanBytes(weight),
+                chain
+                    .iter()
+                    .map(PackageName::as_str)
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            );
+        }
+        for (package, saved) in graph.savings(&base, size).into_iter().take(top) {
+            status!(
+                "{} {} to save {}",
+                "Exclude".cyan(),
+                package.as_str().bold(),
+                HumanBytes(saved)
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(args) = matches.subcommand_matches("rdepends") {
+        let package = PackageName::from(
+            args.get_one::<String>("package")
+                .expect("required")
+                .as_str(),
+        );
+        let scope = if args.get_flag("selection") {
+            graph
+        } else {
+            Graph::of_packages(&index.packages)
+        };
+        if !scope.contains(&package) {
+            return Err(Error::UnknownPackage(package.to_string()).into());
+        }
+        let direct = scope.dependents(&package).collect::<BTreeSet<_>>();
+        let transitive = scope.ancestors(&package);
+        for dependent in transitive.iter() {
+            let kind = if direct.contains(dependent) {
+                "Direct".blue()
+            } else {
+                "Transitive".cyan()
+            };
+            status!("{kind} {}", dependent.as_str().bold());
+        }
+        status!(
+            "{} {} packages depend on {}, {} directly",
+            "Dependents".blue(),
+            transitive.len(),
+            package.as_str().bold(),
+            direct.len()
+        );
+        return Ok(());
     }
 
     // Fetch within the dependency set
-    let packages = graph.topo().cloned().collect::<Vec<_>>();
+    let packages = graph.topo();
+
+    let resolved = packages
+        .iter()
+        .filter(|p| !pruned.contains(*p))
+        .filter_map(|p| mapping.get(p))
+        .collect::<Vec<_>>();
+    if matches.get_flag("dry-run") {
+        for p in resolved.iter() {
+            status!(
+                "{} {} ({} download, {} installed)",
+                "Convert".blue(),
+                p.name.as_str().bold(),
+                HumanBytes(p.package_size),
+                HumanBytes(p.installed_size)
+            );
+        }
+    }
+    status!(
+        "{} {} packages: {} to download, {} once extracted",
+        "Resolved".blue(),
+        resolved.len(),
+        HumanBytes(resolved.iter().map(|p| p.package_size).sum()),
+        HumanBytes(resolved.iter().map(|p| p.installed_size).sum())
+    );
+    if let Some(path) = matches.get_one::<PathBuf>("write-index") {
+        let names = resolved
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<BTreeSet<_>>();
+        let trimmed = index.filtered(|p| names.contains(p.name.as_str()));
+        trimmed.write(
+            BufWriter::new(File::create(path)?),
+            Compression::from_extension(path),
+        )?;
+        status!(
+            "{} {} with {} packages",
+            "Wrote".blue(),
+            path.display().to_string().bold(),
+            trimmed.packages.len()
+        );
+    }
+    if matches.get_flag("dry-run") {
+        return Ok(());
+    }
 
     let total_progress = multi.add(
-        ProgressBar::new(packages.len() as u64).with_style(
+        ProgressBar::new(resolved.len() as u64).with_style(
             ProgressStyle::with_template("\n|{bar:20.cyan/blue}| {pos}/{len}")
                 .unwrap()
                 .progress_chars("##-"),
         ),
     );
     total_progress.tick();
+    let transferred = multi.add(ProgressBar::new_spinner().with_style(
+        ProgressStyle::with_template("{binary_bytes} fetched at {binary_bytes_per_sec}")?,
+    ));
 
-    let packages = packages.iter().filter_map(|p| mapping.get(p));
-    let results: Vec<HashedPackage> = stream::iter(
-        packages.map(|f| async { fetch(&multi, &total_progress, f, &origin, &cache_dir).await }),
+    let downloader = Downloader {
+        fetcher: &fetcher,
+        scheduler: &scheduler,
+        cache_dir: &cache_dir,
+        limit: limit.as_ref(),
+        segment_threshold,
+        transferred: Some(&transferred),
+    };
+    let fetches = stream::iter(
+        resolved
+            .into_iter()
+            .map(|f| async { fetch(&downloader, &multi, &total_progress, f).await }),
     )
     .buffer_unordered(CONCURRENCY_LIMIT)
-    .try_collect()
-    .await?;
+    .try_collect::<Vec<_>>();
+
+    let mut results: Vec<HashedPackage> = interrupt
+        .guard(fetches, &cache_dir, &multi, || {
+            format!(
+                "fetching {}/{} packages",
+                total_progress.position(),
+                total_progress.length().unwrap_or_default()
+            )
+        })
+        .await?;
+    transferred.finish();
+
+    // The index truncates some fields, so prefer the metadata of the archives,
+    // and keep their file listings for splitting subpackages
+    let repack_uri = options.repack_uri.as_ref();
+    let repack_dir = cache_dir.join("repacked");
+    if repack_uri.is_some() && !repack_dir.exists() {
+        create_dir(&repack_dir)?;
+    }
+    let total = results.len();
+    let read = Arc::new(AtomicUsize::new(0));
+    let reading = {
+        let (read, cache_dir, repack) = (read.clone(), cache_dir.clone(), repack_uri.is_some());
+        move || -> Result<_, Error> {
+            for result in results.iter_mut() {
+                let mut archive =
+                    Archive::open(cache_dir.join(fetch::file_name(&result.package)?))?;
+                result.package.apply_metadata(archive.metadata()?);
+                result.files = Some(archive.files()?);
+                if repack {
+                    let name = fetch::file_name(&result.package)?;
+                    let file_name = format!("{}.tar.xz", name.trim_end_matches(".eopkg"));
+                    let hash = archive.repack(repack_dir.join(&file_name))?;
+                    result.payload = Some(Payload { file_name, hash });
+                }
+                read.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(results)
+        }
+    };
+    let results = interrupt
+        .guard_blocking(reading, &cache_dir, &multi, || {
+            format!("reading {}/{total} packages", read.load(Ordering::Relaxed))
+        })
+        .await?;
 
-    // Convert to a hashmap
-    let mut source_buckets: HashMap<String, Vec<&HashedPackage>> = HashMap::new();
+    // Bucket the packages into recipes, in name order for stable output
+    let mut source_buckets: BTreeMap<&str, Vec<&HashedPackage>> = BTreeMap::new();
     for result in results.iter() {
-        let source_name = result.package.source.name.clone();
-        if let Some(bucket) = source_buckets.get_mut(&source_name) {
+        let key = bucketing.key(&result.package);
+        if let Some(bucket) = source_buckets.get_mut(key) {
             bucket.push(result)
         } else {
-            source_buckets.insert(source_name, vec![result]);
+            source_buckets.insert(key, vec![result]);
         };
     }
 
-    let base_dir = PathBuf::from("binary-conversion");
-    if base_dir.exists() {
-        remove_dir_all(&base_dir)?;
+    // Merging updates the previous output in place. Only the default output
+    // is cleared as a whole, a chosen one may be a recipes checkout.
+    let merge = matches.get_flag("merge");
+    let base_dir = matches
+        .get_one::<PathBuf>("output")
+        .expect("default")
+        .clone();
+    let owned = matches.value_source("output") == Some(ValueSource::DefaultValue);
+    if show.is_none() {
+        if base_dir.exists() && owned && !merge {
+            remove_dir_all(&base_dir)?;
+        }
+        create_dir_all(&base_dir)?;
     }
-    create_dir(&base_dir)?;
+
+    let pspec_dir = matches.get_one::<PathBuf>("pspec-dir");
+    // Upstream archives shared between recipes are only downloaded once
+    let mut remote_hashes = BTreeMap::new();
+
+    let mut manifest = Manifest {
+        converter: env!("CARGO_PKG_VERSION").to_string(),
+        recipes: vec![],
+    };
 
     // Conversion time.
+    let shared_options = Arc::new(options.clone());
     for (source, packages) in total_progress.wrap_iter(source_buckets.iter()) {
-        let tree = base_dir.join(source);
-        create_dir(&tree)?;
+        let progress = || {
+            format!(
+                "converting {}/{} recipes",
+                manifest.recipes.len(),
+                source_buckets.len()
+            )
+        };
+        interrupt.check(&multi, progress)?;
+        let path = layout.path(&options.rename(source));
+        let tree = base_dir.join(&path);
         let yml_path = tree.join("stone.yml");
-        let yml = convert(packages.clone(), origin.clone())?;
-        let mut file = File::create(yml_path)?;
-        file.write_all(yml.as_bytes())?;
+        let mut pspec = match pspec_dir {
+            Some(dir) => Pspec::find(dir, &packages[0].package.source.name)?,
+            None => None,
+        };
+        if options.source {
+            for archive in pspec.iter_mut().flat_map(|p| p.source.archives.iter_mut()) {
+                let uri = Url::parse(archive.uri.trim())?;
+                let hash = match remote_hashes.get(&uri) {
+                    Some(hash) => *hash,
+                    None => {
+                        let sha1sum = archive.sha1sum.as_deref();
+                        let hashing = fetch::hash_remote(&fetcher, &uri, sha1sum, limit.as_ref());
+                        let (hash, _) = interrupt
+                            .guard(hashing, &cache_dir, &multi, || format!("hashing {uri}"))
+                            .await?;
+                        remote_hashes.insert(uri, hash);
+                        hash
+                    }
+                };
+                archive.sha256 = Some(hash);
+            }
+        }
+        let converting = {
+            let packages = packages.iter().map(|&p| p.clone()).collect::<Vec<_>>();
+            let (origin, pspec, options) = (origin.clone(), pspec.clone(), shared_options.clone());
+            move || {
+                Ok(convert(
+                    packages.iter().collect(),
+                    origin,
+                    pspec.as_ref(),
+                    &options,
+                )?)
+            }
+        };
+        let converted = interrupt
+            .guard_blocking(converting, &cache_dir, &multi, progress)
+            .await?;
+        for warning in converted.warnings.iter() {
+            total_progress.println(format!("{} {warning}", "Warning".yellow()));
+        }
+        if !converted.relocated.is_empty() {
+            total_progress.println(format!(
+                "{} {} legacy paths of {} into /usr",
+                "Relocated".blue(),
+                converted.relocated.len(),
+                source.bold()
+            ));
+            for relocation in converted.relocated.iter() {
+                total_progress.println(format!("  {relocation}"));
+            }
+        }
+        for pkg in packages.iter() {
+            if let Some(update) = pkg.package.history.latest() {
+                if update.is_security() {
+                    total_progress.println(format!(
+                        "{} {} {}-{}: {}",
+                        "Security".red(),
+                        pkg.package.name.as_str().bold(),
+                        update.version,
+                        update.release,
+                        update.comment.lines().next().unwrap_or_default()
+                    ));
+                }
+            }
+            for comar in pkg.package.comar_scripts() {
+                total_progress.println(format!(
+                    "{} {} ships COMAR script {} ({})",
+                    "Review".yellow(),
+                    pkg.package.name.as_str().bold(),
+                    comar.script,
+                    comar.model
+                ));
+            }
+        }
+        let recipe = if merge && yml_path.exists() {
+            let merged = converter::merge(&std::fs::read_to_string(&yml_path)?, &converted.recipe)?;
+            for warning in merged.warnings.iter() {
+                total_progress.println(format!("{} {warning}", "Warning".yellow()));
+            }
+            merged.recipe
+        } else {
+            converted.recipe
+        };
+        let problems = schema::validate(&recipe);
+        for problem in problems.iter() {
+            total_progress.println(format!("{} {source}: {problem}", "Invalid".red()));
+        }
+        if !problems.is_empty() && matches.get_flag("strict-schema") {
+            return Err(Error::InvalidRecipe(source.to_string()).into());
+        }
+        if show.is_some() {
+            total_progress.finish_and_clear();
+            print!("{recipe}");
+            continue;
+        }
+        let provenance = Provenance {
+            converter: env!("CARGO_PKG_VERSION").to_string(),
+            indexes: snapshots.clone(),
+            packages: converted.origins,
+        };
+        let keep_monitoring = merge && tree.join("monitoring.yaml").exists();
+        write_recipe(
+            &tree,
+            &recipe,
+            Some(converted.monitoring.as_str()).filter(|_| !keep_monitoring),
+            &provenance,
+        )?;
+
+        manifest.recipes.push(converter::ManifestEntry {
+            path,
+            ..converted.entry
+        });
+    }
+
+    if show.is_none() {
+        // List recipes in the order they can be built
+        let order = sources
+            .topo()
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| (name, i))
+            .collect::<BTreeMap<_, _>>();
+        manifest
+            .recipes
+            .sort_by_key(|r| order.get(&r.name).copied().unwrap_or(usize::MAX));
+
+        let mut file = BufWriter::new(File::create(base_dir.join("manifest.json"))?);
+        serde_json::to_writer_pretty(&mut file, &manifest)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+
+        // Recipes of a wave only depend on those of earlier waves. Sources
+        // that were resolved but not converted have no recipe to build.
+        let written = manifest
+            .recipes
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect::<BTreeSet<_>>();
+        let waves = sources
+            .waves()
+            .into_iter()
+            .map(|wave| {
+                wave.into_iter()
+                    .filter(|name| written.contains(name.as_str()))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|wave| !wave.is_empty())
+            .collect::<Vec<_>>();
+        let mut file = BufWriter::new(File::create(base_dir.join("waves.json"))?);
+        serde_json::to_writer_pretty(&mut file, &waves)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        status!(
+            "{} {} recipes in {} build waves",
+            "Scheduled".blue(),
+            manifest.recipes.len(),
+            waves.len()
+        );
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use a_piece_of_pisi::{
+        converter,
+        eopkg::{
+            index::{Dependency, Package, RuntimeDependencies, Source},
+            name::PackageName,
+        },
+    };
+
+    use super::{resolve, Error, Limits, Resolution};
+
+    /// Package of its own source depending on `deps`
+    fn package(name: &str, deps: &[&str]) -> Package {
+        Package {
+            name: name.into(),
+            source: Source {
+                name: name.into(),
+                ..Default::default()
+            },
+            run_deps: Some(RuntimeDependencies {
+                deps: deps
+                    .iter()
+                    .map(|d| Dependency {
+                        value: (*d).into(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Packages in the resolved graph
+    fn nodes(resolved: &Resolution) -> Vec<&str> {
+        resolved.graph.nodes().map(PackageName::as_str).collect()
+    }
+
+    #[test]
+    fn resolution() {
+        let packages = [
+            package("nano", &["ncurses", "ghost"]),
+            package("ncurses", &["glibc"]),
+            package("glibc", &[]),
+        ];
+        let mapping = packages
+            .iter()
+            .map(|p| (p.name.clone(), p))
+            .collect::<BTreeMap<_, _>>();
+        let options = converter::Options::default();
+        let converted = BTreeSet::new();
+        let limits = Limits {
+            converted: &converted,
+            max_depth: None,
+            keep_dbginfo: false,
+            unknown_leaves: false,
+        };
+        let roots = [PackageName::from("nano")];
+
+        // Unknown dependencies are reported with their dependents and dropped
+        let resolved = resolve(&roots, &mapping, &limits, &options).unwrap();
+        let mut all = nodes(&resolved);
+        all.sort();
+        assert_eq!(all, ["glibc", "nano", "ncurses"]);
+        assert_eq!(
+            resolved.unknown,
+            [("ghost".into(), vec!["nano".into()])].into()
+        );
+
+        // ... or kept as leaves
+        let leaves = Limits {
+            unknown_leaves: true,
+            ..limits
+        };
+        let resolved = resolve(&roots, &mapping, &leaves, &options).unwrap();
+        assert!(resolved.graph.contains(&"ghost".into()));
+
+        // Depth limits how many levels of dependencies are followed
+        let shallow = |max_depth| Limits {
+            max_depth: Some(max_depth),
+            ..limits
+        };
+        let resolved = resolve(&roots, &mapping, &shallow(0), &options).unwrap();
+        assert_eq!(nodes(&resolved), ["nano"]);
+        let resolved = resolve(&roots, &mapping, &shallow(1), &options).unwrap();
+        assert!(resolved.graph.contains(&"ncurses".into()));
+        assert!(!resolved.graph.contains(&"glibc".into()));
+
+        // Converted recipes are kept, but not what they depend on
+        let converted = ["ncurses".to_string()].into();
+        let pruning = Limits {
+            converted: &converted,
+            ..limits
+        };
+        let resolved = resolve(&roots, &mapping, &pruning, &options).unwrap();
+        assert!(resolved.graph.contains(&"ncurses".into()));
+        assert!(!resolved.graph.contains(&"glibc".into()));
+        assert_eq!(resolved.pruned, ["ncurses".into()].into());
+
+        // Roots must be in the index
+        let resolved = resolve(&["vim".into()], &mapping, &limits, &options);
+        assert!(matches!(resolved, Err(Error::UnknownPackage(name)) if name == "vim"));
+    }
+}
@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Mirror probing and selection
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::{join_all, select_all};
+use reqwest::header::RANGE;
+use thiserror::Error;
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+use url::Url;
+
+use crate::http::{self, Client};
+
+/// Request the first 256KiB of the probe file
+const PROBE_SIZE: u64 = 256 * 1024;
+
+/// Every eopkg repository publishes its index, so probe that
+const PROBE_PATH: &str = "eopkg-index.xml.xz";
+
+/// Result of probing a single mirror
+#[derive(Debug, Clone)]
+pub struct Benchmark {
+    /// Mirror that was probed
+    pub mirror: Url,
+
+    /// Time until the response headers arrived
+    pub latency: Duration,
+
+    /// Observed transfer rate in bytes per second
+    pub throughput: f64,
+}
+
+/// Ensure the mirror is treated as a directory when joining paths onto it
+pub fn normalize(mut mirror: Url) -> Url {
+    if !mirror.path().ends_with('/') {
+        let path = format!("{}/", mirror.path());
+        mirror.set_path(&path);
+    }
+    mirror
+}
+
+/// Probe a single mirror with a small ranged GET
+///
+/// Local `file://` mirrors only need to exist and always rank first
+pub async fn probe(client: &Client, mirror: &Url) -> Result<Benchmark, Error> {
+    let uri = mirror.join(PROBE_PATH)?;
+    if uri.scheme() == "file" {
+        uri.to_file_path()
+            .map_err(|_| Error::InvalidPath)?
+            .metadata()?;
+        return Ok(Benchmark {
+            mirror: mirror.clone(),
+            latency: Duration::ZERO,
+            throughput: f64::INFINITY,
+        });
+    }
+
+    let start = Instant::now();
+    let request = client
+        .get(uri)
+        .header(RANGE, format!("bytes=0-{}", PROBE_SIZE - 1));
+    let mut response = client.read(request.send()).await?.error_for_status()?;
+    let latency = start.elapsed();
+
+    // Servers ignoring the range get cut off once we have enough
+    let mut received = 0u64;
+    while let Some(chunk) = client.read(response.chunk()).await? {
+        received += chunk.len() as u64;
+        if received >= PROBE_SIZE {
+            break;
+        }
+    }
+    let transfer = start.elapsed().saturating_sub(latency);
+    let throughput = received as f64 / transfer.as_secs_f64().max(0.001);
+
+    Ok(Benchmark {
+        mirror: mirror.clone(),
+        latency,
+        throughput,
+    })
+}
+
+/// Probe all mirrors concurrently, returning the reachable ones fastest
+/// first along with the mirrors that failed
+pub async fn rank(client: &Client, mirrors: &[Url]) -> (Vec<Benchmark>, Vec<(Url, Error)>) {
+    let results = join_all(mirrors.iter().map(|m| probe(client, m))).await;
+
+    let mut ranked = vec![];
+    let mut failed = vec![];
+    for (mirror, result) in mirrors.iter().zip(results) {
+        match result {
+            Ok(benchmark) => ranked.push(benchmark),
+            Err(e) => failed.push((mirror.clone(), e)),
+        }
+    }
+
+    ranked.sort_by(|a, b| {
+        b.throughput
+            .partial_cmp(&a.throughput)
+            .unwrap_or(Ordering::Equal)
+            .then(a.latency.cmp(&b.latency))
+    });
+
+    (ranked, failed)
+}
+
+/// Hands out mirrors for downloads while capping the number of concurrent
+/// connections to each host
+pub struct Scheduler {
+    /// Mirrors in order of preference, with the semaphore of their host
+    mirrors: Vec<(Url, Arc<Semaphore>)>,
+}
+
+impl Scheduler {
+    /// Mirrors on the same host share their `per_host` connections
+    pub fn new(mirrors: &[Url], per_host: usize) -> Self {
+        let mut hosts = BTreeMap::new();
+        let mirrors = mirrors
+            .iter()
+            .map(|m| {
+                let host = format!("{}:{:?}", m.host_str().unwrap_or_default(), m.port());
+                let semaphore = hosts
+                    .entry(host)
+                    .or_insert_with(|| Arc::new(Semaphore::new(per_host.max(1))))
+                    .clone();
+                (m.clone(), semaphore)
+            })
+            .collect();
+
+        Self { mirrors }
+    }
+
+    /// Wait for a free connection, preferring the earliest mirror with
+    /// capacity. The connection is released when the permit is dropped.
+    pub async fn acquire(&self) -> Result<(&Url, OwnedSemaphorePermit), Error> {
+        if self.mirrors.is_empty() {
+            return Err(Error::NoMirror);
+        }
+
+        for (mirror, semaphore) in self.mirrors.iter() {
+            if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+                return Ok((mirror, permit));
+            }
+        }
+
+        let waiting = self
+            .mirrors
+            .iter()
+            .map(|(_, semaphore)| Box::pin(semaphore.clone().acquire_owned()));
+        let (permit, index, _) = select_all(waiting).await;
+        Ok((&self.mirrors[index].0, permit?))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("uri parse: {0}")]
+    URI(#[from] url::ParseError),
+
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("http: {0}")]
+    Http(#[from] http::Error),
+
+    #[error("io: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("invalid file uri")]
+    InvalidPath,
+
+    #[error("scheduler closed: {0}")]
+    Closed(#[from] AcquireError),
+
+    #[error("no mirrors to schedule")]
+    NoMirror,
+}
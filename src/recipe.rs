@@ -0,0 +1,208 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Typed stone.yml recipes, as generated by the converter and as found in
+//! existing recipe trees
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::Mapping;
+
+/// A boulder recipe, serialized as `stone.yml`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Recipe {
+    pub name: String,
+    /// Unquoted versions such as `7.1` are read as numbers by YAML
+    #[serde(deserialize_with = "lenient::string")]
+    pub version: String,
+    pub release: u64,
+    pub homepage: String,
+    /// Each upstream is a single entry map keyed by its URI
+    pub upstreams: Vec<BTreeMap<String, Upstream>>,
+    pub summary: String,
+    pub description: String,
+    pub strip: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub networking: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toolchain: Option<String>,
+    #[serde(deserialize_with = "lenient::list")]
+    pub license: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub component: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub builddeps: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rundeps: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<String>,
+    /// Subpackages split off the main package, each keyed by its name
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<BTreeMap<String, Subpackage>>,
+    /// Build the 32-bit profile too
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub emul32: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setup: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    pub install: String,
+    /// Steps overridden per build target, keyed by the target
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<BTreeMap<String, Profile>>,
+    /// Keys the converter does not generate, kept as written
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Profile {
+    pub install: String,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Subpackage {
+    pub summary: String,
+    /// Only set when it differs from the description of the recipe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rundeps: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<String>,
+    /// Patterns of the paths moved into the subpackage
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(from = "lenient::Upstream")]
+pub struct Upstream {
+    pub unpack: bool,
+    pub hash: String,
+    /// Directory the upstream is unpacked into, relative to the build root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unpackdir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stripdirs: Option<u8>,
+    /// Name the upstream is stored under in the source directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rename: Option<String>,
+}
+
+impl Recipe {
+    /// Parse an existing `stone.yml`
+    pub fn parse(yaml: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(self)
+    }
+}
+
+/// Shorthands accepted by boulder when reading recipes
+mod lenient {
+    use serde::{Deserialize, Deserializer};
+    use serde_yaml::Value;
+
+    /// A scalar of any type, as a string
+    pub fn string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        Ok(match Value::deserialize(deserializer)? {
+            Value::String(s) => s,
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            _ => String::new(),
+        })
+    }
+
+    /// A single string or a list of them
+    pub fn list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum List {
+            One(String),
+            Many(Vec<String>),
+        }
+        Ok(match List::deserialize(deserializer)? {
+            List::One(one) => vec![one],
+            List::Many(many) => many,
+        })
+    }
+
+    /// Upstreams are either fully spelled out or a bare hash, unpacked
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    pub enum Upstream {
+        Hash(String),
+        Full {
+            #[serde(default = "unpack")]
+            unpack: bool,
+            hash: String,
+            unpackdir: Option<String>,
+            stripdirs: Option<u8>,
+            rename: Option<String>,
+        },
+    }
+
+    fn unpack() -> bool {
+        true
+    }
+
+    impl From<Upstream> for super::Upstream {
+        fn from(upstream: Upstream) -> Self {
+            match upstream {
+                Upstream::Hash(hash) => Self {
+                    unpack: true,
+                    hash,
+                    ..Default::default()
+                },
+                Upstream::Full {
+                    unpack,
+                    hash,
+                    unpackdir,
+                    stripdirs,
+                    rename,
+                } => Self {
+                    unpack,
+                    hash,
+                    unpackdir,
+                    stripdirs,
+                    rename,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Recipe;
+
+    #[test]
+    fn round_trip() {
+        let recipe = Recipe::parse(
+            "name: nano\nversion: 7.1\nrelease: 3\nhomepage: https://www.nano-editor.org/\n\
+             upstreams:\n- https://www.nano-editor.org/dist/v7/nano-7.1.tar.xz: 57ba751e\n\
+             summary: Editor\ndescription: Editor\nlicense: GPL-3.0-or-later\n\
+             checkdeps:\n- python3\ninstall: '%make_install'\n",
+        )
+        .unwrap();
+        assert_eq!(recipe.version, "7.1");
+        assert_eq!(recipe.license, ["GPL-3.0-or-later"]);
+        let upstream = &recipe.upstreams[0]["https://www.nano-editor.org/dist/v7/nano-7.1.tar.xz"];
+        assert!(upstream.unpack);
+        assert_eq!(upstream.hash, "57ba751e");
+        assert!(recipe.extra.contains_key("checkdeps"));
+
+        let yaml = recipe.to_yaml().unwrap();
+        assert!(yaml.contains("checkdeps:\n- python3\n"));
+        assert_eq!(Recipe::parse(&yaml).unwrap(), recipe);
+    }
+}
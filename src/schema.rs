@@ -0,0 +1,270 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Checks of generated recipes against the stone.yml schema boulder reads
+
+use std::fmt;
+
+use serde_yaml::{Mapping, Value};
+
+/// Keys boulder requires at the root of a recipe
+const REQUIRED: &[&str] = &[
+    "name",
+    "version",
+    "release",
+    "homepage",
+    "upstreams",
+    "summary",
+    "description",
+    "license",
+];
+
+/// Keys known at the root of a recipe
+const ROOT: &[&str] = &[
+    "name",
+    "version",
+    "release",
+    "homepage",
+    "upstreams",
+    "summary",
+    "description",
+    "license",
+    "component",
+    "strip",
+    "networking",
+    "emul32",
+    "builddeps",
+    "checkdeps",
+    "rundeps",
+    "conflicts",
+    "environment",
+    "setup",
+    "build",
+    "install",
+    "check",
+    "workload",
+    "toolchain",
+    "tuning",
+    "packages",
+    "profiles",
+];
+
+/// Keys known for each subpackage
+const PACKAGE: &[&str] = &["summary", "description", "rundeps", "conflicts", "paths"];
+
+/// Keys known for each build profile
+const PROFILE: &[&str] = &[
+    "environment",
+    "setup",
+    "build",
+    "install",
+    "check",
+    "workload",
+];
+
+/// Keys known for each upstream
+const UPSTREAM: &[&str] = &[
+    "hash",
+    "unpack",
+    "unpackdir",
+    "stripdirs",
+    "rename",
+    "ref",
+    "clonedir",
+];
+
+/// Keys holding a list of strings
+const LISTS: &[&str] = &["builddeps", "checkdeps", "rundeps", "conflicts", "paths"];
+
+/// Keys holding build steps
+const STEPS: &[&str] = &[
+    "environment",
+    "setup",
+    "build",
+    "install",
+    "check",
+    "workload",
+];
+
+/// A reason boulder would reject the recipe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Dotted path to the offending key, such as `packages.%(name)-devel`
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.reason)
+    }
+}
+
+#[derive(Default)]
+struct Validator {
+    problems: Vec<Problem>,
+}
+
+impl Validator {
+    fn report(&mut self, path: &str, reason: impl Into<String>) {
+        self.problems.push(Problem {
+            path: path.to_string(),
+            reason: reason.into(),
+        });
+    }
+
+    /// Check for keys outside of `known` and the type of the known ones
+    fn fields(&mut self, path: &str, map: &Mapping, known: &[&str]) {
+        for (key, value) in map {
+            let Some(key) = key.as_str() else {
+                self.report(path, "non-string key");
+                continue;
+            };
+            let path = join(path, key);
+            if !known.contains(&key) {
+                self.report(&path, "unknown key");
+            } else if LISTS.contains(&key) {
+                self.strings(&path, value);
+            } else if STEPS.contains(&key) && !value.is_string() {
+                self.report(&path, "expected a string");
+            }
+        }
+    }
+
+    fn strings(&mut self, path: &str, value: &Value) {
+        match value.as_sequence() {
+            Some(items) if items.iter().all(Value::is_string) => {}
+            _ => self.report(path, "expected a list of strings"),
+        }
+    }
+
+    /// Entries of a list of single entry maps, such as `packages`
+    fn entries<'a>(&mut self, path: &str, value: &'a Value) -> Vec<(String, &'a Value)> {
+        let Some(items) = value.as_sequence() else {
+            self.report(path, "expected a list");
+            return vec![];
+        };
+        let mut entries = vec![];
+        for item in items {
+            match item.as_mapping().filter(|m| m.len() == 1) {
+                Some(map) => {
+                    let (key, value) = map.iter().next().expect("single entry");
+                    match key.as_str() {
+                        Some(key) => entries.push((join(path, key), value)),
+                        None => self.report(path, "non-string key"),
+                    }
+                }
+                None => self.report(path, "expected single entry maps"),
+            }
+        }
+        entries
+    }
+
+    fn nested(&mut self, path: &str, value: &Value, known: &[&str]) {
+        match value.as_mapping() {
+            Some(map) => self.fields(path, map, known),
+            None => self.report(path, "expected a map"),
+        }
+    }
+
+    fn recipe(&mut self, recipe: &Mapping) {
+        for key in REQUIRED {
+            if !recipe.contains_key(*key) {
+                self.report(key, "missing");
+            }
+        }
+        self.fields("", recipe, ROOT);
+
+        for key in ["name", "version", "homepage", "summary", "description"] {
+            if recipe.get(key).is_some_and(|v| !v.is_string()) {
+                self.report(key, "expected a string");
+            }
+        }
+        if let Some(release) = recipe.get("release") {
+            if release.as_u64().filter(|r| *r > 0).is_none() {
+                self.report("release", "expected a positive integer");
+            }
+        }
+        if let Some(license) = recipe.get("license") {
+            if !license.is_string() {
+                self.strings("license", license);
+            }
+        }
+        for key in ["strip", "networking", "emul32"] {
+            if recipe.get(key).is_some_and(|v| !v.is_bool()) {
+                self.report(key, "expected a boolean");
+            }
+        }
+
+        if let Some(upstreams) = recipe.get("upstreams") {
+            for (path, upstream) in self.entries("upstreams", upstreams) {
+                // A bare hash is shorthand for an unpacked tarball
+                if !upstream.is_string() {
+                    self.nested(&path, upstream, UPSTREAM);
+                }
+            }
+        }
+        if let Some(packages) = recipe.get("packages") {
+            for (path, package) in self.entries("packages", packages) {
+                self.nested(&path, package, PACKAGE);
+            }
+        }
+        if let Some(profiles) = recipe.get("profiles") {
+            for (path, profile) in self.entries("profiles", profiles) {
+                self.nested(&path, profile, PROFILE);
+            }
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+/// Parse a generated `stone.yml` back and list what boulder would reject,
+/// empty when the recipe is valid
+pub fn validate(recipe: &str) -> Vec<Problem> {
+    let mut validator = Validator::default();
+    match serde_yaml::from_str::<Value>(recipe) {
+        Ok(Value::Mapping(recipe)) => validator.recipe(&recipe),
+        Ok(_) => validator.report("", "expected a map"),
+        Err(e) => validator.report("", format!("invalid yaml: {e}")),
+    }
+    validator.problems
+}
+
+#[cfg(test)]
+mod test {
+    use super::validate;
+
+    #[test]
+    fn problems() {
+        let recipe =
+            "name: nano\nversion: '7.2'\nrelease: 0\nhomepage: https://www.nano-editor.org/\n\
+            upstreams:\n- https://example.com/nano.eopkg:\n    hash: '00'\n    unpack: false\n\
+            summary: Editor\ndescription: Editor\nlicense: GPL-3.0-or-later\nrundeps: ncurses\n\
+            packages:\n- '%(name)-devel':\n    paths:\n    - /usr/include\n    provides: []\n\
+            install: |\n  true\nsetup_steps: ''\n";
+        let problems = validate(recipe)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                "rundeps: expected a list of strings",
+                "setup_steps: unknown key",
+                "release: expected a positive integer",
+                "packages.%(name)-devel.provides: unknown key",
+            ]
+        );
+
+        assert_eq!(validate("name: [")[0].path, "");
+        assert_eq!(validate("name: nano\n").len(), 7);
+    }
+}